@@ -0,0 +1,14 @@
+#![no_main]
+
+use falcompress::{bzip, DecodeOptions};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+	let _ = bzip::decompress(data, &mut Vec::new());
+	let _ = bzip::decompress_into(data, &mut [0; 0x1000]);
+	let _ = bzip::decompress_with(
+		data,
+		&mut Vec::new(),
+		DecodeOptions::new().with_max_output(0x1000),
+	);
+});
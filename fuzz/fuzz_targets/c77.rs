@@ -0,0 +1,15 @@
+#![no_main]
+
+use falcompress::{c77, DecodeOptions};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+	let _ = c77::inspect(data);
+	let _ = c77::decompress(data, &mut Vec::new());
+	let _ = c77::decompress_into(data, &mut [0; 0x1000]);
+	let _ = c77::decompress_with(
+		data,
+		&mut Vec::new(),
+		DecodeOptions::new().with_max_output(0x1000),
+	);
+});
@@ -0,0 +1,14 @@
+#![no_main]
+
+use falcompress::DecodeOptions;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+	let _ = falcompress::detect(data);
+	let _ = falcompress::detect_with(data, DecodeOptions::new().with_max_output(0x1000));
+	let _ = falcompress::decompress_auto_with(
+		data,
+		&mut Vec::new(),
+		DecodeOptions::new().with_max_output(0x1000),
+	);
+});
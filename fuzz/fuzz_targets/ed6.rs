@@ -0,0 +1,18 @@
+#![no_main]
+
+use falcompress::{ed6, DecodeOptions};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+	let _ = ed6::inspect(data);
+	let _ = ed6::decompress(data, &mut Vec::new());
+	let _ = ed6::decompress_into(data, &mut [0; 0x1000]);
+	let _ = ed6::decompress_with(
+		data,
+		&mut Vec::new(),
+		DecodeOptions::new().with_max_output(0x1000),
+	);
+	for chunk in ed6::chunks(data).flatten() {
+		let _ = chunk.decompress(&mut Vec::new());
+	}
+});
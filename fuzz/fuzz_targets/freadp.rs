@@ -0,0 +1,15 @@
+#![no_main]
+
+use falcompress::{ed7, DecodeOptions};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+	let _ = ed7::inspect_freadp(data);
+	let _ = ed7::freadp(data, &mut Vec::new());
+	let _ = ed7::freadp_into(data, &mut [0; 0x1000]);
+	let _ = ed7::freadp_with(
+		data,
+		&mut Vec::new(),
+		DecodeOptions::new().with_max_output(0x1000),
+	);
+});
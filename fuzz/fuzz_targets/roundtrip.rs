@@ -0,0 +1,101 @@
+#![no_main]
+
+use falcompress::bzip::{CompressLevel, CompressMode, Compressor};
+use falcompress::{bzip, c77, ed6, ed7, Error};
+use libfuzzer_sys::fuzz_target;
+
+// The first byte selects the format and mode, the rest is the data to compress.
+fuzz_target!(|data: &[u8]| {
+	let Some((&selector, data)) = data.split_first() else {
+		return;
+	};
+	let mode = if selector & 1 == 0 {
+		CompressMode::Mode1
+	} else {
+		CompressMode::Mode2
+	};
+	let mut out = Vec::new();
+	match selector >> 1 & 7 {
+		0 => {
+			// Mode 2 can't handle more than 0xFFFF bytes in a single chunk.
+			let data = &data[..data.len().min(0xFFF0)];
+			let level = if selector & 0x10 == 0 {
+				CompressLevel::Falcom
+			} else {
+				CompressLevel::Best
+			};
+			let mut compressed = Vec::new();
+			Compressor::with_level(level).compress(data, &mut compressed, mode);
+			assert_eq!(
+				bzip::decompress(&compressed, &mut out).unwrap(),
+				compressed.len()
+			);
+			assert_eq!(out, data);
+			return;
+		}
+		1 => {
+			let Some(compressed) = fits(ed6::try_compress(data, mode)) else {
+				return;
+			};
+			assert_eq!(
+				ed6::decompress(&compressed, &mut out).unwrap(),
+				compressed.len()
+			);
+		}
+		2 => {
+			let Some(compressed) = fits(ed6::try_compress_smallest(data)) else {
+				return;
+			};
+			assert_eq!(
+				ed6::decompress(&compressed, &mut out).unwrap(),
+				compressed.len()
+			);
+		}
+		3 => {
+			let compressed = ed7::compress(data, mode);
+			assert_eq!(
+				ed7::decompress(&compressed, &mut out).unwrap(),
+				compressed.len()
+			);
+		}
+		4 => {
+			let compressed = c77::compress(data);
+			assert_eq!(
+				c77::decompress(&compressed, &mut out).unwrap(),
+				compressed.len()
+			);
+		}
+		5 => {
+			let compressed = c77::compress_mode(data, u32::from(selector >> 4));
+			assert_eq!(
+				c77::decompress(&compressed, &mut out).unwrap(),
+				compressed.len()
+			);
+		}
+		_ => {
+			let chunk_size = usize::from(selector >> 4) * 0x100 + 1;
+			let mode = ed7::FreadpMode::C77 {
+				chunk_size,
+				mode: 8,
+			};
+			let compressed = ed7::freadp_compress(data, mode);
+			assert_eq!(
+				ed7::freadp(&compressed, &mut out).unwrap(),
+				compressed.len()
+			);
+		}
+	}
+	assert_eq!(out, data);
+});
+
+/// Incompressible ed6 chunks don't fit in their length prefix, which is the only error compression can return.
+fn fits(compressed: falcompress::Result<Vec<u8>>) -> Option<Vec<u8>> {
+	match compressed {
+		Ok(compressed) => Some(compressed),
+		Err(Error::ChunkTooLarge { len }) => {
+			assert!(len > 0xFFFD);
+			None
+		}
+		Err(e) => panic!("{e}"),
+	}
+}
@@ -1,3 +1,10 @@
+use alloc::format;
+use alloc::vec::Vec;
+
+use super::TokenKind;
+use crate::{Error, Result};
+
+mod matches;
 mod mode1;
 mod mode2;
 
@@ -8,9 +15,161 @@ pub enum CompressMode {
 	Mode2,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum CompressLevel {
+	/// Gives output identical to Falcom's compressor.
+	#[default]
+	Falcom,
+	/// Gives the smallest output this crate can find, at the cost of speed.
+	Best,
+}
+
 pub fn compress(input: &[u8], out: &mut Vec<u8>, mode: CompressMode) {
-	match mode {
-		CompressMode::Mode1 => mode1::compress(input, out),
-		CompressMode::Mode2 => mode2::compress(input, out),
+	Compressor::new().compress(input, out, mode)
+}
+
+#[derive(Default)]
+pub struct Compressor {
+	level: CompressLevel,
+	mode1: mode1::Cache,
+	mode2: Option<mode2::Tables>,
+	best: matches::Finder,
+}
+
+impl Compressor {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn with_level(level: CompressLevel) -> Self {
+		Compressor {
+			level,
+			..Self::default()
+		}
+	}
+
+	pub fn compress(&mut self, input: &[u8], out: &mut Vec<u8>, mode: CompressMode) {
+		match (mode, self.level) {
+			(CompressMode::Mode1, CompressLevel::Best) => {
+				mode1::compress_best(input, out, &mut self.best)
+			}
+			(CompressMode::Mode1, CompressLevel::Falcom) => {
+				mode1::compress(input, out, &mut self.mode1)
+			}
+			(CompressMode::Mode2, CompressLevel::Best) => {
+				mode2::compress_best(input, out, &mut self.best)
+			}
+			(CompressMode::Mode2, CompressLevel::Falcom) => {
+				let tables = self.mode2.get_or_insert_with(mode2::Tables::new);
+				mode2::compress(input, out, tables)
+			}
+		}
+	}
+}
+
+pub struct Encoder<'a> {
+	state: State<'a>,
+	out_pos: usize,
+	last_offset: usize,
+	ended: bool,
+}
+
+enum State<'a> {
+	Mode1(&'a mut Vec<u8>),
+	Mode2(mode2::Bits<'a>),
+}
+
+impl<'a> Encoder<'a> {
+	pub fn new(out: &'a mut Vec<u8>, mode: CompressMode) -> Self {
+		let state = match mode {
+			CompressMode::Mode1 => State::Mode1(out),
+			CompressMode::Mode2 => State::Mode2(mode2::Bits::new(out)),
+		};
+		Encoder {
+			state,
+			out_pos: 0,
+			last_offset: 0,
+			ended: false,
+		}
+	}
+
+	/// The number of bytes that the tokens written so far decompress to.
+	pub fn out_pos(&self) -> usize {
+		self.out_pos
+	}
+
+	pub fn write(&mut self, token: TokenKind) -> Result<()> {
+		let invalid = |what: &str| {
+			Err(Error::Custom {
+				message: format!("cannot write {token:?}: {what}"),
+			})
+		};
+		if self.ended {
+			return invalid("the stream has already ended");
+		}
+		if let TokenKind::Repeat { offset, count } | TokenKind::RepeatLast { offset, count } = token
+		{
+			if offset == 0 || offset > self.out_pos {
+				return Err(Error::BadRepeat {
+					count,
+					offset,
+					len: self.out_pos,
+				});
+			}
+		}
+		match &mut self.state {
+			State::Mode1(out) => match token {
+				// An empty literal is a zero byte, which would make a mode 1 stream start like a mode 2 one.
+				TokenKind::Literal(data) if (1..1 << 13).contains(&data.len()) => {
+					mode1::write_literal(out, data)
+				}
+				TokenKind::Constant { byte, count } if (4..(1 << 12) + 4).contains(&count) => {
+					mode1::write_const(out, byte, count)
+				}
+				TokenKind::Repeat { offset, count }
+					if offset < 1 << 13 && (4..8).contains(&count) =>
+				{
+					mode1::write_repeat_short(out, offset, count);
+					self.last_offset = offset;
+				}
+				TokenKind::RepeatLast { offset, .. } if offset != self.last_offset => {
+					return invalid("the offset differs from the previous repeat");
+				}
+				TokenKind::RepeatLast { count, .. } if count < 1 << 5 => {
+					mode1::write_repeat_last(out, count)
+				}
+				TokenKind::End => return invalid("mode 1 has no end marker"),
+				_ => return invalid("out of range for mode 1"),
+			},
+			State::Mode2(b) => match token {
+				TokenKind::Literal(&[v]) => mode2::write_literal(b, v),
+				TokenKind::Repeat { offset, count }
+					if offset < 1 << 13 && (2..(1 << 8) + 14).contains(&count) =>
+				{
+					mode2::write_repeat(b, offset, count)
+				}
+				TokenKind::Constant { byte, count } if (14..(1 << 12) + 14).contains(&count) => {
+					mode2::write_constant(b, byte, count)
+				}
+				TokenKind::End => {
+					mode2::write_end(b);
+					self.ended = true;
+				}
+				TokenKind::Literal(_) => return invalid("mode 2 literals are a single byte"),
+				TokenKind::RepeatLast { .. } => return invalid("mode 2 has no repeat-last token"),
+				_ => return invalid("out of range for mode 2"),
+			},
+		}
+		self.out_pos += token.out_len();
+		Ok(())
+	}
+
+	/// Writes the end marker in mode 2, unless it has already been written.
+	pub fn finish(mut self) {
+		if let State::Mode2(b) = &mut self.state {
+			if !self.ended {
+				mode2::write_end(b);
+			}
+		}
 	}
 }
@@ -0,0 +1,61 @@
+use alloc::vec::Vec;
+
+use crate::util::count_equal;
+
+/// The longest matches at a position, among the offsets that fit in 8 bits, and among all of them.
+#[derive(Clone, Copy, Default)]
+pub struct Match {
+	pub short_len: u16,
+	pub short_offset: u16,
+	pub long_len: u16,
+	pub long_offset: u16,
+}
+
+/// Hash chains over the digraphs in the input, kept between calls so that their buffers can be reused.
+#[derive(Default)]
+pub struct Finder {
+	head: Vec<u32>,
+	prev: Vec<u32>,
+	matches: Vec<Match>,
+}
+
+impl Finder {
+	/// Finds the longest matches at each position within the 13-bit window, up to 269 bytes, the longest that mode 2 can express.
+	pub fn find(&mut self, input: &[u8]) -> &[Match] {
+		const NONE: u32 = u32::MAX;
+		let Finder {
+			head,
+			prev,
+			matches,
+		} = self;
+		head.clear();
+		head.resize(0x10000, NONE);
+		prev.clear();
+		prev.resize(input.len(), NONE);
+		matches.clear();
+		matches.resize(input.len(), Match::default());
+		for i in 0..input.len().saturating_sub(1) {
+			let dig = u16::from_le_bytes([input[i], input[i + 1]]) as usize;
+			let max = (input.len() - i).min(269);
+			let m = &mut matches[i];
+			let mut p = head[dig];
+			while p != NONE && i - p as usize <= 0x1FFF {
+				let offset = i - p as usize;
+				let len = count_equal(&input[i..], &input[p as usize..], max);
+				if len > m.long_len as usize {
+					(m.long_len, m.long_offset) = (len as u16, offset as u16);
+					if offset < 256 {
+						(m.short_len, m.short_offset) = (len as u16, offset as u16);
+					}
+					if len == max {
+						break;
+					}
+				}
+				p = prev[p as usize];
+			}
+			prev[i] = head[dig];
+			head[dig] = i as u32;
+		}
+		matches
+	}
+}
@@ -2,28 +2,78 @@
 
 // This compressor was reversed by hand. It's way simpler than the mode 2 one.
 
-use std::collections::{HashMap, VecDeque};
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
 
+use super::matches::Finder;
 use crate::util::count_equal;
 
-pub fn compress(input: &[u8], out: &mut Vec<u8>) {
+const NONE: u32 = u32::MAX;
+
+/// Hash chains over the 7-byte sequences in the input.
+pub struct Cache {
+	head: Vec<u32>,
+	prev: Vec<u32>,
+}
+
+impl Default for Cache {
+	fn default() -> Self {
+		Cache {
+			head: vec![NONE; 1 << 15],
+			prev: Vec::new(),
+		}
+	}
+}
+
+impl Cache {
+	fn hash(key: &[u8]) -> usize {
+		let mut k = [0; 8];
+		k[..7].copy_from_slice(key);
+		(u64::from_le_bytes(k).wrapping_mul(0x9E3779B97F4A7C15) >> 49) as usize
+	}
+
+	fn insert(&mut self, input: &[u8], pos: usize) {
+		if let Some(key) = input.get(pos..pos + 7) {
+			let h = Self::hash(key);
+			self.prev[pos] = self.head[h];
+			self.head[h] = pos as u32;
+		}
+	}
+
+	/// Finds the earliest of the longest matches within the window, if it is longer than `min_len`.
+	fn find(&self, input: &[u8], pos: usize, min_len: usize) -> Option<(usize, usize)> {
+		let key = input.get(pos..pos + 7)?;
+		let mut best = None;
+		let mut best_len = min_len;
+		let mut rep_pos = self.head[Self::hash(key)];
+		while rep_pos != NONE && pos - rep_pos as usize <= 0x1FFF {
+			let p = rep_pos as usize;
+			if &input[p..p + 7] == key {
+				let rep_len = count_equal(&input[pos + 7..], &input[p + 7..], usize::MAX) + 7;
+				// Chains go from newest to oldest, but Falcom's prefers the oldest.
+				if rep_len > best_len || best.is_some() && rep_len == best_len {
+					(best_len, best) = (rep_len, Some(p));
+				}
+			}
+			rep_pos = self.prev[p];
+		}
+		Some((best_len, best?))
+	}
+}
+
+pub fn compress(input: &[u8], out: &mut Vec<u8>, cache: &mut Cache) {
 	let mut input_pos = 0;
 	let mut last = 0;
-	let mut cache = HashMap::<[u8; 7], VecDeque<usize>>::new();
+	cache.head.fill(NONE);
+	cache.prev.resize(input.len(), NONE);
 	let mut w = 0;
 	while input_pos < input.len() {
 		let mut run_len =
-			1 + count_equal(&input[input_pos..], &input[input_pos + 1..], (1 << 12) + 3) + 1;
+			count_equal(&input[input_pos..], &input[input_pos + 1..], (1 << 12) + 2) + 1;
 		let mut run_pos = input_pos;
-		if let Some(input_slice) = input.get(input_pos..input_pos + 7) {
-			let input_slice = <[u8; 7]>::try_from(input_slice).unwrap();
-			for rep_pos in cache.entry(input_slice).or_default() {
-				let rep_len =
-					count_equal(&input[input_pos + 7..], &input[*rep_pos + 7..], usize::MAX) + 7;
-				if rep_len > run_len {
-					(run_len, run_pos) = (rep_len, *rep_pos);
-				}
-			}
+		if let Some((rep_len, rep_pos)) = cache.find(input, input_pos, run_len) {
+			(run_len, run_pos) = (rep_len, rep_pos);
 		}
 
 		if run_len >= 7 {
@@ -41,45 +91,208 @@ pub fn compress(input: &[u8], out: &mut Vec<u8>) {
 		}
 
 		while w < input_pos {
-			if let Some(input_slice) = input.get(w..w + 7) {
-				let input_slice = input_slice.try_into().unwrap();
-				cache.entry(input_slice).or_default().push_back(w);
-			}
-			if let Some(prev_pos) = w.checked_sub(0x1FFF) {
-				let prev_slice = &input[prev_pos..prev_pos + 7];
-				let prev_slice = prev_slice.try_into().unwrap();
-				cache.entry(prev_slice).or_default().pop_front();
-			}
+			cache.insert(input, w);
 			w += 1;
 		}
 	}
 	write_verb(out, &input[last..input_pos]);
 }
 
+/// Compresses with a cost-based parse, using the short repeats and repeat-last tokens that Falcom's never emits.
+///
+/// The only state carried between tokens is the last offset, so this tracks the one on the cheapest path to each position.
+pub fn compress_best(input: &[u8], out: &mut Vec<u8>, finder: &mut Finder) {
+	// Longer matches are continued with repeat-last tokens, which loses nothing at multiples of 31.
+	const MAX_REPEAT: usize = 31 * 8;
+	let n = input.len();
+	let matches = finder.find(input);
+	let mut nodes = vec![
+		Node {
+			cost: u32::MAX,
+			from: 0,
+			step: Step::Literal,
+			last: 0
+		};
+		n + 1
+	];
+	nodes[0].cost = 0;
+
+	// Literal runs shorter than 32 bytes have a one-byte header, and longer ones a two-byte header.
+	let mut short_lits = MinWindow::default();
+	let mut long_lits = MinWindow::default();
+	let mut run = 0;
+	for j in 0..=n {
+		if j >= 1 {
+			short_lits.push(j - 1, nodes[j - 1].cost as i64 - (j - 1) as i64);
+		}
+		if j >= 32 {
+			long_lits.push(j - 32, nodes[j - 32].cost as i64 - (j - 32) as i64);
+		}
+		for (lits, max_len, header) in [(&mut short_lits, 31, 1), (&mut long_lits, 0x1FFF, 2)] {
+			if let Some((i, cost)) = lits.min(j.saturating_sub(max_len)) {
+				let cost = (cost + j as i64 + header) as u32;
+				if cost < nodes[j].cost {
+					nodes[j] = Node {
+						cost,
+						from: i as u32,
+						step: Step::Literal,
+						last: nodes[i].last,
+					};
+				}
+			}
+		}
+		if j == n {
+			break;
+		}
+
+		let base = nodes[j].cost;
+		let last = nodes[j].last;
+		let mut relax = |len: usize, cost: u32, step: Step| {
+			let node = &mut nodes[j + len];
+			if base + cost < node.cost {
+				let last = match step {
+					Step::Repeat(offset) => offset,
+					_ => last,
+				};
+				*node = Node {
+					cost: base + cost,
+					from: j as u32,
+					step,
+					last,
+				};
+			}
+		};
+
+		run = if run > 1 {
+			run - 1
+		} else {
+			count_equal(&input[j..], &input[j + 1..], (1 << 12) + 2) + 1
+		};
+		let max = run.min(MAX_REPEAT);
+		for len in (4..=max).chain((run > max).then_some(run)) {
+			relax(len, if len < 20 { 2 } else { 3 }, Step::Constant);
+		}
+		if last != 0 {
+			let offset = last as usize;
+			let max = count_equal(&input[j..], &input[j - offset..], MAX_REPEAT);
+			for len in 1..=max {
+				relax(len, len.div_ceil(0x1F) as u32, Step::RepeatLast);
+			}
+		}
+		let m = matches[j];
+		for len in 4..=(m.long_len as usize).min(MAX_REPEAT + 7) {
+			relax(
+				len,
+				2 + len.saturating_sub(7).div_ceil(0x1F) as u32,
+				Step::Repeat(m.long_offset),
+			);
+		}
+	}
+
+	let mut path = Vec::new();
+	let mut j = n;
+	while j > 0 {
+		let node = nodes[j];
+		path.push((node.from as usize, j, node.step));
+		j = node.from as usize;
+	}
+	for &(i, j, step) in path.iter().rev() {
+		match step {
+			Step::Literal => write_verb(out, &input[i..j]),
+			Step::Constant => write_const(out, input[i], j - i),
+			Step::Repeat(offset) => {
+				let len = (j - i).min(7);
+				write_repeat_short(out, offset as usize, len);
+				write_repeat_lasts(out, j - i - len);
+			}
+			Step::RepeatLast => write_repeat_lasts(out, j - i),
+		}
+	}
+}
+
+#[derive(Clone, Copy)]
+struct Node {
+	cost: u32,
+	from: u32,
+	step: Step,
+	/// The offset of the last repeat on the path to this position.
+	last: u16,
+}
+
+#[derive(Clone, Copy)]
+enum Step {
+	Literal,
+	Constant,
+	Repeat(u16),
+	RepeatLast,
+}
+
+/// A sliding window minimum over `(position, cost)` pairs, pushed in increasing position.
+#[derive(Default)]
+struct MinWindow(VecDeque<(usize, i64)>);
+
+impl MinWindow {
+	fn push(&mut self, pos: usize, cost: i64) {
+		while self.0.back().is_some_and(|&(_, c)| c >= cost) {
+			self.0.pop_back();
+		}
+		self.0.push_back((pos, cost));
+	}
+
+	/// The cheapest pair at or after `start`.
+	fn min(&mut self, start: usize) -> Option<(usize, i64)> {
+		while self.0.front().is_some_and(|&(p, _)| p < start) {
+			self.0.pop_front();
+		}
+		self.0.front().copied()
+	}
+}
+
+fn write_repeat_lasts(out: &mut Vec<u8>, mut len: usize) {
+	while len > 0 {
+		write_repeat_last(out, len.min(0x1F));
+		len -= len.min(0x1F);
+	}
+}
+
 fn write_verb(out: &mut Vec<u8>, input: &[u8]) {
 	for w in input.chunks(0x1FFF) {
-		write_head(out, 0b00_000000, 5, w.len());
-		out.extend_from_slice(w);
+		write_literal(out, w);
 	}
 }
 
-fn write_const(out: &mut Vec<u8>, b: u8, len: usize) {
+pub fn write_literal(out: &mut Vec<u8>, input: &[u8]) {
+	write_head(out, 0b00_000000, 5, input.len());
+	out.extend_from_slice(input);
+}
+
+pub fn write_const(out: &mut Vec<u8>, b: u8, len: usize) {
 	write_head(out, 0b010_00000, 4, len - 4);
 	out.push(b);
 }
 
 fn write_repeat(out: &mut Vec<u8>, off: usize, mut len: usize) {
 	assert!(len >= 7); // technically supports 4, but not used
-	assert!(off < (1 << 13));
-	out.push(0b1_11_00000 | (off >> 8) as u8);
-	out.push(off as u8);
+	write_repeat_short(out, off, 7);
 	len -= 7;
 	while len > 0 {
-		out.push(0b011_00000 | len.min(0x1F) as u8);
+		write_repeat_last(out, len.min(0x1F));
 		len = len.saturating_sub(0x1F);
 	}
 }
 
+pub fn write_repeat_short(out: &mut Vec<u8>, off: usize, len: usize) {
+	assert!((4..8).contains(&len));
+	assert!(off < (1 << 13));
+	out.push(0b1_00_00000 | ((len - 4) << 5) as u8 | (off >> 8) as u8);
+	out.push(off as u8);
+}
+
+pub fn write_repeat_last(out: &mut Vec<u8>, len: usize) {
+	assert!(len < (1 << 5));
+	out.push(0b011_00000 | len as u8);
+}
+
 fn write_head(out: &mut Vec<u8>, mask: u8, bits: usize, len: usize) {
 	assert!(mask & ((1 << (bits + 1)) - 1) == 0);
 	assert!(len < (1 << (8 + bits)), "{len} < (1<<{})", 8 + bits);
@@ -3,13 +3,18 @@
 // but this one gives identical results to Falcom's,
 // which I value higher than saving a few bytes.
 
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::matches::Finder;
 use crate::util::count_equal;
 
-pub fn compress(input: &[u8], out: &mut Vec<u8>) {
+pub fn compress(input: &[u8], out: &mut Vec<u8>, tables: &mut Tables) {
 	assert!(input.len() < 0xFFFF);
 	let mut input_pos = 0;
 	let mut b = Bits::new(out);
-	let mut dig = Digraphs::new(input);
+	let mut dig = Digraphs::new(input, tables);
 	while input_pos < input.len() {
 		let mut run_len = count_equal(&input[input_pos..], &input[input_pos + 1..], 0xFFE) + 1;
 		if run_len < 14 {
@@ -22,50 +27,14 @@ pub fn compress(input: &[u8], out: &mut Vec<u8>) {
 		}
 
 		assert!(run_len > 0);
-		if b.bit(run_len > 1) {
-			if run_pos == input_pos {
-				run_len = run_len.min((1<<12)-1+14);
-				b.bit(true);
-				b.bits(13, 1);
-				let n = run_len - 14;
-				if b.bit(n >= 16) {
-					b.bits(12, n);
-				} else {
-					b.bits(4, n);
-				}
-				b.byte(input[input_pos]);
-			} else {
-				run_len = run_len.min((1<<8)-1+14);
-				let n = input_pos - run_pos;
-				if b.bit(n >= 256) {
-					b.bits(13, n);
-				} else {
-					b.bits(8, n);
-				}
-
-				let m = run_len;
-				if m >= 3 {
-					b.bit(false);
-				}
-				if m >= 4 {
-					b.bit(false);
-				}
-				if m >= 5 {
-					b.bit(false);
-				}
-				if m >= 6 {
-					b.bit(false);
-				}
-				if b.bit(m < 14) {
-					if m >= 6 {
-						b.bits(3, m - 6);
-					}
-				} else {
-					b.bits(8, m - 14);
-				}
-			}
+		if run_len == 1 {
+			write_literal(&mut b, input[input_pos]);
+		} else if run_pos == input_pos {
+			run_len = run_len.min((1 << 12) - 1 + 14);
+			write_constant(&mut b, input[input_pos], run_len);
 		} else {
-			b.byte(input[input_pos]);
+			run_len = run_len.min((1 << 8) - 1 + 14);
+			write_repeat(&mut b, input_pos - run_pos, run_len);
 		}
 
 		for _ in 0..run_len {
@@ -73,27 +42,242 @@ pub fn compress(input: &[u8], out: &mut Vec<u8>) {
 			dig.advance();
 		}
 	}
+	write_end(&mut b);
+}
+
+/// Compresses with an optimal parse over the whole window, minimizing the number of bits rather than matching Falcom's.
+pub fn compress_best(input: &[u8], out: &mut Vec<u8>, finder: &mut Finder) {
+	assert!(input.len() < 0xFFFF);
+	let n = input.len();
+	let matches = finder.find(input);
+
+	// Cheapest encoding of input[i..] in bits, filled from the back.
+	let mut best = MinTree::new(n + 1);
+	best.set(n, 0);
+	let mut steps = vec![(Step::Literal, 1); n];
+	let mut run = 0;
+	for i in (0..n).rev() {
+		run = if input.get(i + 1) == Some(&input[i]) {
+			run + 1
+		} else {
+			1
+		};
+		let mut choice = (best.get(i + 1) + 9, Step::Literal, 1);
+		let mut consider = |lo: usize, hi: usize, cost: u32, step: Step| {
+			if lo <= hi {
+				let (c, j) = best.min(i + lo, i + hi + 1);
+				if c + cost < choice.0 {
+					choice = (c + cost, step, j - i);
+				}
+			}
+		};
+
+		if run >= 14 {
+			consider(14, run.min(29), 2 + 13 + 1 + 4 + 8, Step::Constant);
+			consider(
+				30,
+				run.min((1 << 12) - 1 + 14),
+				2 + 13 + 1 + 12 + 8,
+				Step::Constant,
+			);
+		}
+		let m = matches[i];
+		for (lo, hi, bits) in COUNT_BITS {
+			let short = m.short_len as usize;
+			let long = m.long_len as usize;
+			consider(
+				lo,
+				hi.min(short),
+				2 + 8 + bits,
+				Step::Repeat(m.short_offset),
+			);
+			consider(
+				lo.max(short + 1),
+				hi.min(long),
+				2 + 13 + bits,
+				Step::Repeat(m.long_offset),
+			);
+		}
+		let (cost, step, len) = choice;
+		best.set(i, cost);
+		steps[i] = (step, len);
+	}
+
+	let mut b = Bits::new(out);
+	let mut i = 0;
+	while i < n {
+		let (step, len) = steps[i];
+		match step {
+			Step::Literal => write_literal(&mut b, input[i]),
+			Step::Constant => write_constant(&mut b, input[i], len),
+			Step::Repeat(offset) => write_repeat(&mut b, offset as usize, len),
+		}
+		i += len;
+	}
+	write_end(&mut b);
+}
+
+/// The ranges of repeat counts that share the same encoded length, and that length in bits.
+const COUNT_BITS: [(usize, usize, u32); 6] = [
+	(2, 2, 1),
+	(3, 3, 2),
+	(4, 4, 3),
+	(5, 5, 4),
+	(6, 13, 8),
+	(14, 269, 13),
+];
+
+#[derive(Clone, Copy)]
+enum Step {
+	Literal,
+	Constant,
+	Repeat(u16),
+}
+
+/// A segment tree over the costs, to find the cheapest position in a range.
+struct MinTree {
+	size: usize,
+	tree: Vec<(u32, u32)>,
+}
+
+impl MinTree {
+	fn new(len: usize) -> Self {
+		let size = len.next_power_of_two();
+		MinTree {
+			size,
+			tree: vec![(u32::MAX / 2, u32::MAX); 2 * size],
+		}
+	}
+
+	fn get(&self, pos: usize) -> u32 {
+		self.tree[self.size + pos].0
+	}
+
+	fn set(&mut self, pos: usize, cost: u32) {
+		let mut k = self.size + pos;
+		self.tree[k] = (cost, pos as u32);
+		while k > 1 {
+			k /= 2;
+			self.tree[k] = self.tree[2 * k].min(self.tree[2 * k + 1]);
+		}
+	}
+
+	/// The cheapest cost in `start..end`, and its position.
+	fn min(&self, start: usize, end: usize) -> (u32, usize) {
+		let mut best = (u32::MAX / 2, u32::MAX);
+		let (mut l, mut r) = (start + self.size, end + self.size);
+		while l < r {
+			if l % 2 == 1 {
+				best = best.min(self.tree[l]);
+				l += 1;
+			}
+			if r % 2 == 1 {
+				r -= 1;
+				best = best.min(self.tree[r]);
+			}
+			l /= 2;
+			r /= 2;
+		}
+		(best.0, best.1 as usize)
+	}
+}
+
+pub fn write_literal(b: &mut Bits, v: u8) {
+	b.bit(false);
+	b.byte(v);
+}
+
+/// Writes a repeat, using the short form if the offset fits in 8 bits, like Falcom's does.
+pub fn write_repeat(b: &mut Bits, offset: usize, count: usize) {
+	assert!((2..(1 << 8) + 14).contains(&count));
+	b.bit(true);
+	if b.bit(offset >= 256) {
+		b.bits(13, offset);
+	} else {
+		b.bits(8, offset);
+	}
+
+	let m = count;
+	if m >= 3 {
+		b.bit(false);
+	}
+	if m >= 4 {
+		b.bit(false);
+	}
+	if m >= 5 {
+		b.bit(false);
+	}
+	if m >= 6 {
+		b.bit(false);
+	}
+	if b.bit(m < 14) {
+		if m >= 6 {
+			b.bits(3, m - 6);
+		}
+	} else {
+		b.bits(8, m - 14);
+	}
+}
+
+/// Writes a constant run, using the short form if the count fits in 4 bits, like Falcom's does.
+pub fn write_constant(b: &mut Bits, v: u8, count: usize) {
+	assert!((14..(1 << 12) + 14).contains(&count));
+	b.bit(true);
+	b.bit(true);
+	b.bits(13, 1);
+	let n = count - 14;
+	if b.bit(n >= 16) {
+		b.bits(12, n);
+	} else {
+		b.bits(4, n);
+	}
+	b.byte(v);
+}
+
+pub fn write_end(b: &mut Bits) {
 	b.bit(true);
 	b.bit(true);
 	b.bits(13, 0);
 }
 
+pub struct Tables {
+	head: Box<[u16; 0x10000]>,
+	next: Box<[u16; 0x2000]>, // Falcom's is 0x8000, but that doesn't bring any benefits
+	tail: Box<[u16; 0x10000]>,
+}
+
+impl Tables {
+	pub fn new() -> Self {
+		fn table<const N: usize>() -> Box<[u16; N]> {
+			vec![0xFFFF; N].into_boxed_slice().try_into().unwrap()
+		}
+		Tables {
+			head: table(),
+			next: table(),
+			tail: table(),
+		}
+	}
+}
+
 struct Digraphs<'a> {
 	input: &'a [u8],
 	pos: usize,
-	head: [u16; 0x10000],
-	next: [u16; 0x2000], // Falcom's is 0x8000, but that doesn't bring any benefits
-	tail: [u16; 0x10000],
+	head: &'a mut [u16; 0x10000],
+	next: &'a mut [u16; 0x2000],
+	tail: &'a mut [u16; 0x10000],
 }
 
-impl Digraphs<'_> {
-	fn new(input: &[u8]) -> Digraphs<'_> {
+impl<'a> Digraphs<'a> {
+	fn new(input: &'a [u8], tables: &'a mut Tables) -> Digraphs<'a> {
+		// Only `head` needs to be cleared: `tail` and `next` are only read for positions that
+		// are reachable from `head`, and those are always written first.
+		tables.head.fill(0xFFFF);
 		Digraphs {
 			input,
 			pos: 0,
-			head: [0xFFFF; 0x10000],
-			next: [0xFFFF; 0x2000],
-			tail: [0xFFFF; 0x10000],
+			head: &mut tables.head,
+			next: &mut tables.next,
+			tail: &mut tables.tail,
 		}
 	}
 
@@ -137,14 +321,14 @@ impl Digraphs<'_> {
 	}
 }
 
-struct Bits<'a> {
+pub struct Bits<'a> {
 	out: &'a mut Vec<u8>,
 	bit_mask: u16,
 	bitpos: usize,
 }
 
 impl<'a> Bits<'a> {
-	fn new(out: &'a mut Vec<u8>) -> Self {
+	pub fn new(out: &'a mut Vec<u8>) -> Self {
 		let bitpos = out.len();
 		out.extend([0, 0]);
 		Self {
@@ -1,9 +1,11 @@
-use gospel::read::{Le, Reader};
+use alloc::vec::Vec;
 
-use crate::util::OutBuf;
-use crate::{Error, Result};
+use crate::util::{OutBuf, Output, Reader};
+use crate::{DecodeOptions, Error, Result};
 
+#[derive(Debug, Clone)]
 struct Bits {
+	word_pos: usize,
 	bits: u16,
 	// Zero's decompressor counts number of remaining bits instead,
 	// but this method is simpler.
@@ -13,6 +15,7 @@ struct Bits {
 impl Bits {
 	fn new() -> Self {
 		Bits {
+			word_pos: 0,
 			bits: 0,
 			nextbit: 0,
 		}
@@ -28,6 +31,7 @@ impl Bits {
 	}
 
 	fn renew_bits(&mut self, f: &mut Reader) -> Result<()> {
+		self.word_pos = f.pos();
 		self.bits = f.u16()?;
 		self.nextbit = 1;
 		Ok(())
@@ -61,6 +65,191 @@ impl Bits {
 	}
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token<'a> {
+	pub kind: TokenKind<'a>,
+	/// The input position where the token starts, that is, where the previous token ended.
+	pub pos: usize,
+	/// In mode 2, the position of the flag word that holds the token's first flag bit, and the index of that bit.
+	/// The bit may be in a word before `pos`, or in one starting at `pos`.
+	pub bit: Option<(usize, u32)>,
+	/// The number of bytes written by the earlier tokens in the chunk.
+	pub out_pos: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind<'a> {
+	/// Bytes copied verbatim. In mode 2, this is always a single byte.
+	Literal(&'a [u8]),
+	/// Repeats `count` bytes, starting `offset` bytes back.
+	Repeat { offset: usize, count: usize },
+	/// Writes `count` copies of `byte`.
+	Constant { byte: u8, count: usize },
+	/// In mode 1, a repeat with the same offset as the previous one, which is included here for convenience.
+	RepeatLast { offset: usize, count: usize },
+	/// In mode 2, the end of the stream.
+	End,
+}
+
+impl TokenKind<'_> {
+	/// The number of bytes written by the token.
+	pub fn out_len(&self) -> usize {
+		match *self {
+			TokenKind::Literal(data) => data.len(),
+			TokenKind::Repeat { count, .. } => count,
+			TokenKind::Constant { count, .. } => count,
+			TokenKind::RepeatLast { count, .. } => count,
+			TokenKind::End => 0,
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Tokens<'a> {
+	f: Reader<'a>,
+	state: State,
+	out_pos: usize,
+	done: bool,
+}
+
+#[derive(Debug, Clone)]
+enum State {
+	Mode1 { last_o: usize },
+	Mode2 { b: Bits },
+}
+
+pub fn tokens(data: &[u8]) -> Tokens<'_> {
+	let state = if data.first() == Some(&0) {
+		State::Mode2 { b: Bits::new() }
+	} else {
+		State::Mode1 { last_o: 0 }
+	};
+	Tokens {
+		f: Reader::new(data),
+		state,
+		out_pos: 0,
+		done: false,
+	}
+}
+
+impl<'a> Tokens<'a> {
+	/// The position in the input after the tokens read so far.
+	pub fn pos(&self) -> usize {
+		self.f.pos()
+	}
+
+	fn next_mode2(f: &mut Reader<'a>, b: &mut Bits) -> Result<TokenKind<'a>> {
+		if f.pos() == 0 {
+			b.renew_bits(f)?;
+			b.nextbit <<= 8;
+		}
+
+		Ok(if !b.bit(f)? {
+			TokenKind::Literal(f.slice(1)?)
+		} else if !b.bit(f)? {
+			let offset = b.bits(8, f)?;
+			let count = b.read_count(f)?;
+			TokenKind::Repeat { offset, count }
+		} else {
+			match b.bits(13, f)? {
+				0 => TokenKind::End,
+				1 => {
+					let n = if b.bit(f)? {
+						b.bits(12, f)?
+					} else {
+						b.bits(4, f)?
+					};
+					TokenKind::Constant {
+						byte: f.u8()?,
+						count: 14 + n,
+					}
+				}
+				offset => {
+					let count = b.read_count(f)?;
+					TokenKind::Repeat { offset, count }
+				}
+			}
+		})
+	}
+
+	#[bitmatch::bitmatch]
+	fn next_mode1(f: &mut Reader<'a>, last_o: &mut usize) -> Result<TokenKind<'a>> {
+		let kind;
+		#[bitmatch]
+		match f.u8()? as usize {
+			"00xnnnnn" => {
+				let n = if x == 1 { n << 8 | f.u8()? as usize } else { n };
+				kind = TokenKind::Literal(f.slice(n)?);
+			}
+			"010xnnnn" => {
+				let n = if x == 1 { n << 8 | f.u8()? as usize } else { n };
+				kind = TokenKind::Constant {
+					byte: f.u8()?,
+					count: 4 + n,
+				};
+			}
+			"011nnnnn" => {
+				kind = TokenKind::RepeatLast {
+					offset: *last_o,
+					count: n,
+				};
+			}
+			"1nnooooo" => {
+				*last_o = o << 8 | f.u8()? as usize;
+				kind = TokenKind::Repeat {
+					offset: *last_o,
+					count: 4 + n,
+				};
+			}
+		}
+		Ok(kind)
+	}
+}
+
+impl<'a> Iterator for Tokens<'a> {
+	type Item = Result<Token<'a>>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.done {
+			return None;
+		}
+		let pos = self.f.pos();
+		let (kind, bit) = match &mut self.state {
+			State::Mode1 { .. } if self.f.is_empty() => {
+				self.done = true;
+				return None;
+			}
+			State::Mode1 { last_o } => (Self::next_mode1(&mut self.f, last_o), None),
+			State::Mode2 { b } => {
+				let bit = match b.nextbit {
+					0 if pos == 0 => (0, 8),
+					0 => (pos, 0),
+					_ => (b.word_pos, b.nextbit.trailing_zeros()),
+				};
+				(Self::next_mode2(&mut self.f, b), Some(bit))
+			}
+		};
+		let kind = match kind {
+			Ok(kind) => kind,
+			Err(e) => {
+				self.done = true;
+				return Some(Err(e));
+			}
+		};
+		self.done = kind == TokenKind::End;
+		let out_pos = self.out_pos;
+		self.out_pos += kind.out_len();
+		Some(Ok(Token {
+			kind,
+			pos,
+			bit,
+			out_pos,
+		}))
+	}
+}
+
+impl core::iter::FusedIterator for Tokens<'_> {}
+
 fn decompress_mode2(data: &[u8], mut w: OutBuf) -> Result<usize, Error> {
 	let f = &mut Reader::new(data);
 	let mut b = Bits::new();
@@ -69,7 +258,7 @@ fn decompress_mode2(data: &[u8], mut w: OutBuf) -> Result<usize, Error> {
 
 	loop {
 		if !b.bit(f)? {
-			w.extend(f.slice(1)?)
+			w.extend(f.slice(1)?)?
 		} else if !b.bit(f)? {
 			let o = b.bits(8, f)?;
 			let n = b.read_count(f)?;
@@ -83,7 +272,7 @@ fn decompress_mode2(data: &[u8], mut w: OutBuf) -> Result<usize, Error> {
 					} else {
 						b.bits(4, f)?
 					};
-					w.decomp_constant(14 + n, f.u8()?);
+					w.decomp_constant(14 + n, f.u8()?)?;
 				}
 				o => {
 					let n = b.read_count(f)?;
@@ -105,11 +294,11 @@ fn decompress_mode1(data: &[u8], mut w: OutBuf) -> Result<usize, Error> {
 		match f.u8()? as usize {
 			"00xnnnnn" => {
 				let n = if x == 1 { n << 8 | f.u8()? as usize } else { n };
-				w.extend(f.slice(n)?);
+				w.extend(f.slice(n)?)?;
 			}
 			"010xnnnn" => {
 				let n = if x == 1 { n << 8 | f.u8()? as usize } else { n };
-				w.decomp_constant(4 + n, f.u8()?);
+				w.decomp_constant(4 + n, f.u8()?)?;
 			}
 			"011nnnnn" => {
 				w.decomp_repeat(n, last_o)?;
@@ -124,9 +313,21 @@ fn decompress_mode1(data: &[u8], mut w: OutBuf) -> Result<usize, Error> {
 }
 
 pub fn decompress(data: &[u8], w: &mut Vec<u8>) -> Result<usize> {
+	decompress_to(data, &mut w.into())
+}
+
+pub fn decompress_into(data: &[u8], w: &mut [u8]) -> Result<(usize, usize)> {
+	crate::util::decompress_into(w, |w| decompress_to(data, w))
+}
+
+pub fn decompress_with(data: &[u8], w: &mut Vec<u8>, options: DecodeOptions) -> Result<usize> {
+	crate::util::decompress_with(w, options, |w| decompress_to(data, w))
+}
+
+pub(crate) fn decompress_to(data: &[u8], w: &mut Output) -> Result<usize> {
 	if data.first() == Some(&0) {
-		decompress_mode2(data, w.into())
+		decompress_mode2(data, OutBuf::new(w))
 	} else {
-		decompress_mode1(data, w.into())
+		decompress_mode1(data, OutBuf::new(w))
 	}
 }
@@ -23,8 +23,36 @@ mod decompress;
 /// In most cases you will likely want to use the framed formats instead, [`crate::ed6`] or [`crate::ed7`].
 pub use decompress::decompress;
 
+/// Like [`decompress`], but writes into a fixed-size buffer instead of a `Vec`.
+///
+/// Returns the number of bytes read and written, or [`Error::OutputLimit`](crate::Error::OutputLimit)
+/// if the output does not fit in the buffer.
+pub use decompress::decompress_into;
+
+pub(crate) use decompress::decompress_to;
+/// Like [`decompress`], but with options such as a limit on the output size.
+///
+/// Fails with [`Error::OutputLimit`](crate::Error::OutputLimit) as soon as the output would exceed
+/// `options.max_output`, rather than following a long run of repeat tokens.
+pub use decompress::decompress_with;
+
+/// Disassembles a single chunk of compressed data into its tokens, for debugging.
+///
+/// Decoding stops after the first error, or after the end token in mode 2.
+/// Replaying the tokens in order produces the same output as [`decompress`].
+pub use decompress::tokens;
+
+/// A single token in a bzip stream, as returned by [`tokens`].
+pub use decompress::Token;
+
+/// The kind of a [`Token`].
+pub use decompress::TokenKind;
+
+/// An iterator over the tokens in a bzip stream, returned by [`tokens`].
+pub use decompress::Tokens;
+
 /// Compresses a single chunk of compressed data, in the specified mode.
-/// The mode 2 compressor can currently not handle chunks larger than `0xFFFF` bytes,
+/// The mode 2 compressor can currently only handle chunks smaller than `0xFFFF` bytes,
 /// but mode 1 has no such restrictions.
 /// Usually, chunks no larger than `0xFFF0` bytes are used, in either mode.
 ///
@@ -32,10 +60,278 @@ pub use decompress::decompress;
 pub use compress::compress;
 pub use compress::CompressMode;
 
+/// Writes an explicit sequence of tokens, for use with custom match finders.
+///
+/// Each token is written as exactly one token in the canonical form that Falcom's compressor uses,
+/// so [`tokens`] returns the same tokens when reading the output back.
+/// Tokens that the mode cannot express, such as offsets of `0x2000` or more, are rejected with an error,
+/// and so are repeats that reach back before the start of the output.
+///
+/// In mode 1, literals must not be empty,
+/// and [`TokenKind::RepeatLast`] must have the same offset as the previous repeat.
+/// In mode 2, literals must be a single byte, and the end marker is written by [`Encoder::finish`]
+/// unless a [`TokenKind::End`] token has already been written.
+pub use compress::Encoder;
+
+/// Holds the tables used by the compressor, so that they can be reused between chunks.
+///
+/// The mode 2 tables are about 272 KiB, so they are kept on the heap and only allocated when first needed.
+/// [`compress`] creates a new one for each call, so prefer this when compressing many chunks.
+/// It can also be created with a [`CompressLevel`], using [`Compressor::with_level`].
+pub use compress::Compressor;
+
+/// How hard the compressor tries. The default, [`CompressLevel::Falcom`], gives output identical to Falcom's.
+///
+/// [`CompressLevel::Best`] finds the cheapest sequence of tokens over the whole window,
+/// which usually saves a few percent, but is many times slower.
+/// In mode 1, it also uses the short repeats and repeat-last tokens that Falcom's compressor never emits.
+pub use compress::CompressLevel;
+
+#[test]
+fn compressor_should_match_fresh() {
+	let mut compressor = Compressor::new();
+	let mut data = (0..0x20000u32)
+		.map(|a| (a.wrapping_mul(a) >> 11) as u8)
+		.collect::<Vec<_>>();
+	data[0x11000..0x12000].fill(0);
+	for mode in [CompressMode::Mode1, CompressMode::Mode2] {
+		for chunk in data.chunks(0xFFF0).chain(data.chunks(0x3000)) {
+			let mut fresh = Vec::new();
+			compress(chunk, &mut fresh, mode);
+			let mut reused = Vec::new();
+			compressor.compress(chunk, &mut reused, mode);
+			assert!(fresh == reused);
+		}
+	}
+}
+
+#[test]
+fn mode1_should_not_extend_short_runs() {
+	// One byte too short for a constant token, followed by a different byte.
+	let data = [7, 7, 7, 7, 7, 7, 8];
+	let mut compressed = Vec::new();
+	compress(&data, &mut compressed, CompressMode::Mode1);
+	let mut out = Vec::new();
+	assert_eq!(decompress(&compressed, &mut out).unwrap(), compressed.len());
+	assert_eq!(out, data);
+}
+
+#[test]
+fn best_should_not_be_larger() {
+	let mut data = crate::util::test_data(0xFFF0);
+	data[0x1000..0x2000].fill(7);
+	for mode in [CompressMode::Mode1, CompressMode::Mode2] {
+		let mut falcom = Vec::new();
+		compress(&data, &mut falcom, mode);
+		let mut best = Vec::new();
+		Compressor::with_level(CompressLevel::Best).compress(&data, &mut best, mode);
+		assert!(
+			best.len() < falcom.len(),
+			"{} < {}",
+			best.len(),
+			falcom.len()
+		);
+		let mut out = Vec::new();
+		assert_eq!(decompress(&best, &mut out).unwrap(), best.len());
+		assert!(out == data);
+	}
+}
+
+#[test]
+fn best_compressor_should_match_fresh() {
+	let mut compressor = Compressor::with_level(CompressLevel::Best);
+	let mut data = crate::util::test_data(0x6000);
+	data[0x1000..0x1800].fill(7);
+	for mode in [CompressMode::Mode1, CompressMode::Mode2] {
+		for chunk in data.chunks(0x4000).chain(data.chunks(0x1000)) {
+			let mut fresh = Vec::new();
+			Compressor::with_level(CompressLevel::Best).compress(chunk, &mut fresh, mode);
+			let mut reused = Vec::new();
+			compressor.compress(chunk, &mut reused, mode);
+			assert!(fresh == reused);
+		}
+	}
+}
+
+#[test]
+fn decompress_with_should_stop_at_limit() {
+	use crate::{DecodeOptions, Error};
+
+	let mut compressed = Vec::new();
+	compress(&[0; 100000], &mut compressed, CompressMode::Mode1);
+	let mut out = vec![1, 2, 3];
+	let options = DecodeOptions::new().with_max_output(1000);
+	assert!(matches!(
+		decompress_with(&compressed, &mut out, options),
+		Err(Error::OutputLimit { limit: 1000 }),
+	));
+	assert!(out.len() <= 1003);
+
+	out.truncate(3);
+	let options = DecodeOptions::new().with_max_output(100000);
+	assert_eq!(
+		decompress_with(&compressed, &mut out, options).unwrap(),
+		compressed.len()
+	);
+	assert_eq!(out.len(), 100003);
+}
+
+#[test]
+fn tokens_should_replay_to_output() {
+	let mut data = crate::util::test_data(0x8000);
+	data[0x1000..0x2000].fill(7);
+	for mode in [CompressMode::Mode1, CompressMode::Mode2] {
+		let mut compressed = Vec::new();
+		compress(&data, &mut compressed, mode);
+		let mut out = Vec::new();
+		let mut tokens = tokens(&compressed);
+		let mut last = None;
+		for token in &mut tokens {
+			let token = token.unwrap();
+			assert_eq!(token.out_pos, out.len());
+			assert_eq!(token.bit.is_some(), mode == CompressMode::Mode2);
+			match token.kind {
+				TokenKind::Literal(data) => out.extend_from_slice(data),
+				TokenKind::Repeat { offset, count } | TokenKind::RepeatLast { offset, count } => {
+					for _ in 0..count {
+						out.push(out[out.len() - offset]);
+					}
+				}
+				TokenKind::Constant { byte, count } => out.resize(out.len() + count, byte),
+				TokenKind::End => {}
+			}
+			last = Some(token.kind);
+		}
+		assert!(out == data);
+		assert_eq!(tokens.pos(), compressed.len());
+		assert_eq!(last == Some(TokenKind::End), mode == CompressMode::Mode2);
+	}
+}
+
+#[test]
+fn encoder_should_reproduce_tokens() {
+	let mut data = crate::util::test_data(0x8000);
+	data[0x1000..0x2000].fill(7);
+	for mode in [CompressMode::Mode1, CompressMode::Mode2] {
+		let mut compressed = Vec::new();
+		compress(&data, &mut compressed, mode);
+		let mut out = Vec::new();
+		let mut encoder = Encoder::new(&mut out, mode);
+		for token in tokens(&compressed) {
+			encoder.write(token.unwrap().kind).unwrap();
+		}
+		assert_eq!(encoder.out_pos(), data.len());
+		encoder.finish();
+		assert!(out == compressed);
+	}
+}
+
+#[test]
+fn encoder_should_reject_empty_literals() {
+	let data = crate::util::test_data(0x1000);
+	for mode in [CompressMode::Mode1, CompressMode::Mode2] {
+		let mut compressed = Vec::new();
+		compress(&data, &mut compressed, mode);
+		let mut out = Vec::new();
+		let mut encoder = Encoder::new(&mut out, mode);
+		let empty = core::iter::once(TokenKind::Literal(&[]));
+		for (i, kind) in empty
+			.chain(tokens(&compressed).map(|t| t.unwrap().kind))
+			.enumerate()
+		{
+			assert_eq!(encoder.write(kind).is_ok(), i != 0);
+		}
+		encoder.finish();
+		let mut decompressed = Vec::new();
+		assert_eq!(decompress(&out, &mut decompressed).unwrap(), out.len());
+		assert!(decompressed == data);
+	}
+}
+
+#[test]
+fn encoder_should_reject_unrepresentable() {
+	use crate::Error;
+
+	let mut out = Vec::new();
+	let mut encoder = Encoder::new(&mut out, CompressMode::Mode2);
+	encoder
+		.write(TokenKind::Constant {
+			byte: 1,
+			count: 0x3000,
+		})
+		.unwrap_err();
+	encoder.write(TokenKind::Literal(&[1, 2])).unwrap_err();
+	encoder
+		.write(TokenKind::Constant {
+			byte: 1,
+			count: 0x100D,
+		})
+		.unwrap();
+	assert!(matches!(
+		encoder.write(TokenKind::Repeat {
+			offset: 0x100E,
+			count: 2
+		}),
+		Err(Error::BadRepeat {
+			offset: 0x100E,
+			len: 0x100D,
+			..
+		}),
+	));
+	encoder
+		.write(TokenKind::Repeat {
+			offset: 0x1000,
+			count: 270,
+		})
+		.unwrap_err();
+	encoder
+		.write(TokenKind::RepeatLast {
+			offset: 0x1000,
+			count: 2,
+		})
+		.unwrap_err();
+	encoder.write(TokenKind::End).unwrap();
+	encoder.write(TokenKind::Literal(&[1])).unwrap_err();
+
+	let mut out = Vec::new();
+	let mut encoder = Encoder::new(&mut out, CompressMode::Mode1);
+	encoder.write(TokenKind::Literal(&[0; 0x2000])).unwrap_err();
+	encoder.write(TokenKind::Literal(&[0; 0x1FFF])).unwrap();
+	encoder
+		.write(TokenKind::Repeat {
+			offset: 0x1FFF,
+			count: 8,
+		})
+		.unwrap_err();
+	encoder
+		.write(TokenKind::Repeat {
+			offset: 0x1FFF,
+			count: 4,
+		})
+		.unwrap();
+	encoder
+		.write(TokenKind::RepeatLast {
+			offset: 0x1FFE,
+			count: 4,
+		})
+		.unwrap_err();
+	encoder
+		.write(TokenKind::RepeatLast {
+			offset: 0x1FFF,
+			count: 31,
+		})
+		.unwrap();
+	encoder.write(TokenKind::End).unwrap_err();
+	encoder.finish();
+	let mut decompressed = Vec::new();
+	assert_eq!(decompress(&out, &mut decompressed).unwrap(), out.len());
+	assert!(decompressed == [0; 0x1FFF + 35]);
+}
+
 #[test]
-#[ignore = "it is slow"]
+#[ignore = "requires game data, and is slow"]
 fn mode2_should_roundtrip() {
-	use gospel::read::{Le as _, Reader};
+	use crate::util::Reader;
 
 	let data = std::fs::read("../data/fc.extract2/00/font64._da").unwrap();
 	let mut f = Reader::new(&data);
@@ -47,7 +343,7 @@ fn mode2_should_roundtrip() {
 		let chunklen = f.u16().unwrap() as usize - 2;
 		let inchunk = f.slice(chunklen).unwrap();
 		assert!(inchunk[0] == 0);
-		println!("{} / {}", f.pos(), f.len());
+		println!("{} / {}", f.pos(), data.len());
 
 		let mut chunk = Vec::new();
 		let start = std::time::Instant::now();
@@ -78,8 +374,9 @@ fn mode2_should_roundtrip() {
 }
 
 #[test]
+#[ignore = "requires game data"]
 fn mode1_should_roundtrip() {
-	use gospel::read::{Le as _, Reader};
+	use crate::util::Reader;
 
 	let data = std::fs::read("../data/3rd.extract2/33/val2._x3").unwrap();
 	let mut f = Reader::new(&data);
@@ -91,7 +388,7 @@ fn mode1_should_roundtrip() {
 		let chunklen = f.u16().unwrap() as usize - 2;
 		let inchunk = f.slice(chunklen).unwrap();
 		assert!(inchunk[0] != 0);
-		println!("{} / {}", f.pos(), f.len());
+		println!("{} / {}", f.pos(), data.len());
 
 		let mut chunk = Vec::new();
 		let start = std::time::Instant::now();
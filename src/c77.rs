@@ -1,34 +1,230 @@
-use gospel::read::{Le as _, Reader};
+use alloc::vec;
+use alloc::vec::Vec;
 
-use crate::util::{count_equal, OutBuf};
-use crate::{Error, Result};
+#[cfg(test)]
+use crate::util::test_data;
+use crate::util::{self, count_equal, OutBuf, Output, Reader};
+use crate::{DecodeOptions, Error, Result};
 
 pub fn decompress(data: &[u8], out: &mut Vec<u8>) -> Result<usize> {
+	decompress_to(data, &mut out.into())
+}
+
+/// Like [`decompress`], but writes into a fixed-size buffer instead of a `Vec`.
+///
+/// Returns the number of bytes read and written, or [`Error::OutputLimit`] if the output does not fit in the buffer.
+pub fn decompress_into(data: &[u8], out: &mut [u8]) -> Result<(usize, usize)> {
+	util::decompress_into(out, |out| decompress_to(data, out))
+}
+
+/// Like [`decompress`], but with options such as a limit on the output size.
+///
+/// Fails with [`Error::OutputLimit`] as soon as the output would exceed `options.max_output`.
+pub fn decompress_with(data: &[u8], out: &mut Vec<u8>, options: DecodeOptions) -> Result<usize> {
+	util::decompress_with(out, options, |out| decompress_to(data, out))
+}
+
+pub(crate) fn decompress_to(data: &[u8], out: &mut Output) -> Result<usize> {
 	let f = &mut Reader::new(data);
 	let in_size = f.u32()? as usize;
 	let out_size = f.u32()? as usize;
-	let expected_in_pos = f.pos() + in_size;
-	let expected_out_pos = out.len() + out_size;
-	decompress_inner(f.slice(in_size)?, out.into())?;
+	let expected_in_pos = f.pos().saturating_add(in_size);
+	let expected_out_pos = out.len().saturating_add(out_size);
+	let inner = f.slice(in_size)?;
+	out.declared("c77 out_pos", expected_out_pos, |out| {
+		decompress_inner(inner, OutBuf::new(out))
+	})?;
 	Error::check_size("c77 in_pos", expected_in_pos, f.pos())?;
 	Error::check_size("c77 out_pos", expected_out_pos, out.len())?;
 	Ok(f.pos())
 }
 
+/// Information about a C77 stream, as returned by [`inspect`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Info {
+	pub in_size: usize,
+	pub out_size: usize,
+	/// The mode word. 0 means stored, 8 means byte-split, and other values below 16
+	/// split a `u16` into a count of `mode` bits and an offset of the remaining bits.
+	pub mode: u32,
+}
+
+/// Reads the header of a C77 stream without decompressing it.
+pub fn inspect(data: &[u8]) -> Option<Info> {
+	let f = &mut Reader::new(data);
+	let info = read_info(f)?;
+	f.is_empty().then_some(info)
+}
+
+pub(crate) fn read_info(f: &mut Reader) -> Option<Info> {
+	let in_size = f.u32().ok()? as usize;
+	let out_size = f.u32().ok()? as usize;
+	let f = &mut Reader::new(f.slice(in_size).ok()?);
+	let mode = f.u32().ok()?;
+	if mode >= 16 || mode == 0 && f.remaining().len() != out_size {
+		return None;
+	}
+	Some(Info {
+		in_size,
+		out_size,
+		mode,
+	})
+}
+
+/// A single token in a C77 stream, as returned by [`tokens`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token<'a> {
+	pub kind: TokenKind<'a>,
+	/// The input position where the token starts.
+	pub pos: usize,
+	/// The number of bytes written by the earlier tokens in the stream.
+	pub out_pos: usize,
+}
+
+/// The kind of a [`Token`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind<'a> {
+	/// Bytes copied verbatim. In mode 0, the whole stream is a single raw token.
+	Raw(&'a [u8]),
+	/// Repeats `count` bytes, starting `offset` bytes back, followed by a single literal byte.
+	Repeat {
+		offset: usize,
+		count: usize,
+		literal: u8,
+	},
+}
+
+impl TokenKind<'_> {
+	/// The number of bytes written by the token.
+	pub fn out_len(&self) -> usize {
+		match *self {
+			TokenKind::Raw(data) => data.len(),
+			TokenKind::Repeat { count, .. } => count + 1,
+		}
+	}
+}
+
+/// An iterator over the tokens in a C77 stream, returned by [`tokens`].
+#[derive(Debug, Clone)]
+pub struct Tokens<'a> {
+	f: Reader<'a>,
+	/// The position of `f` within the whole stream.
+	base: usize,
+	/// Whether the size header has yet to be read.
+	header: bool,
+	mode: Option<u32>,
+	out_pos: usize,
+	done: bool,
+}
+
+/// Disassembles a C77 stream into its tokens, for debugging.
+///
+/// Decoding stops after the first error. Replaying the tokens in order produces the same output as [`decompress`].
+pub fn tokens(data: &[u8]) -> Tokens<'_> {
+	Tokens {
+		f: Reader::new(data),
+		base: 0,
+		header: true,
+		mode: None,
+		out_pos: 0,
+		done: false,
+	}
+}
+
+impl<'a> Tokens<'a> {
+	/// The position in the input after the tokens read so far.
+	pub fn pos(&self) -> usize {
+		self.base + self.f.pos()
+	}
+
+	/// Reads the header and mode word, if they have not been read yet.
+	fn mode(&mut self) -> Result<u32> {
+		if self.header {
+			let in_size = self.f.u32()? as usize;
+			self.f.u32()?;
+			let inner = self.f.slice(in_size)?;
+			self.base = self.f.pos() - in_size;
+			self.f = Reader::new(inner);
+			self.header = false;
+		}
+		if let Some(mode) = self.mode {
+			return Ok(mode);
+		}
+		let mode = self.f.u32()?;
+		if mode >= 16 {
+			return Err(Error::Custom {
+				message: alloc::format!("unsupported compression mode: {}", mode),
+			});
+		}
+		Ok(*self.mode.insert(mode))
+	}
+
+	fn next_kind(&mut self, mode: u32) -> Result<TokenKind<'a>> {
+		let f = &mut self.f;
+		let (count, x) = match mode {
+			0 => return f.slice(f.remaining().len()).map(TokenKind::Raw),
+			8 => (f.u8()? as usize, f.u8()? as usize),
+			_ => {
+				let x = f.u16()? as usize;
+				(x & !(!0 << mode), x >> mode)
+			}
+		};
+		Ok(if count == 0 {
+			TokenKind::Raw(f.slice(x)?)
+		} else {
+			TokenKind::Repeat {
+				offset: x + 1,
+				count,
+				literal: f.u8()?,
+			}
+		})
+	}
+}
+
+impl<'a> Iterator for Tokens<'a> {
+	type Item = Result<Token<'a>>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.done {
+			return None;
+		}
+		let kind = self.mode().and_then(|mode| {
+			if self.f.is_empty() {
+				self.done = true;
+				return Ok(None);
+			}
+			Ok(Some((self.pos(), self.next_kind(mode)?)))
+		});
+		let (pos, kind) = match kind {
+			Ok(Some(token)) => token,
+			Ok(None) => return None,
+			Err(e) => {
+				self.done = true;
+				return Some(Err(e));
+			}
+		};
+		let out_pos = self.out_pos;
+		self.out_pos += kind.out_len();
+		Some(Ok(Token { kind, pos, out_pos }))
+	}
+}
+
+impl core::iter::FusedIterator for Tokens<'_> {}
+
 fn decompress_inner(data: &[u8], mut out: OutBuf) -> Result<()> {
 	let mut f = Reader::new(data);
 	let mode = f.u32()?;
 	if mode == 0 {
-		out.extend(f.remaining());
+		out.extend(f.remaining())?;
 	} else if mode == 8 {
 		while !f.is_empty() {
 			let x1 = f.u8()? as usize;
 			let x2 = f.u8()? as usize;
 			if x1 == 0 {
-				out.extend(f.slice(x2)?);
+				out.extend(f.slice(x2)?)?;
 			} else {
 				out.decomp_repeat(x1, x2 + 1)?;
-				out.extend(&[f.u8()?]);
+				out.extend(&[f.u8()?])?;
 			}
 		}
 	} else if mode < 16 {
@@ -37,52 +233,305 @@ fn decompress_inner(data: &[u8], mut out: OutBuf) -> Result<()> {
 			let x1 = x & !(!0 << mode);
 			let x2 = x >> mode;
 			if x1 == 0 {
-				out.extend(f.slice(x2)?);
+				out.extend(f.slice(x2)?)?;
 			} else {
 				out.decomp_repeat(x1, x2 + 1)?;
-				out.extend(&[f.u8()?]);
+				out.extend(&[f.u8()?])?;
 			}
 		}
 	} else {
-		return Err(Error::Custom { message: format!("unsupported compression mode: {}", mode) });
+		return Err(Error::Custom {
+			message: alloc::format!("unsupported compression mode: {}", mode),
+		});
 	}
 	Ok(())
 }
 
-// Only supports mode 8, but that's the only one the game uses anyway so
+/// Compresses data into a C77 stream, as read by [`decompress`].
+///
+/// This uses mode 8, the only mode known to be used by the games.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+	compress_mode(data, 8)
+}
+
+/// Compresses data into a C77 stream using the given mode, which must be less than 16.
+///
+/// Mode 0 stores the data uncompressed. Other modes split each token into `mode` bits of count
+/// and `16 - mode` bits of offset, so lower modes allow more distant but shorter repeats.
+/// Mode 1 can only express repeats of a single byte, which never save space,
+/// so it stores the data as raw runs, slightly larger than mode 0.
+pub fn compress_mode(data: &[u8], mode: u32) -> Vec<u8> {
+	let mut f = Vec::new();
+	f.extend(0u32.to_le_bytes());
+	f.extend((data.len() as u32).to_le_bytes());
+	f.extend(mode.to_le_bytes());
+	compress_inner_mode(data, &mut f, mode);
+	let in_size = f.len() as u32 - 8;
+	f[..4].copy_from_slice(&in_size.to_le_bytes());
+	f
+}
+
+/// Compresses data in mode 8, without the header or mode word.
 pub fn compress_inner(input: &[u8], out: &mut Vec<u8>) {
-	fn encode_raw(last: &mut usize, i: usize, out: &mut Vec<u8>, input: &[u8]) {
+	compress_inner_mode(input, out, 8)
+}
+
+/// Compresses data in the given mode, without the header or mode word.
+///
+/// The mode 8 output is identical to Falcom's; other modes use the same heuristics scaled to their limits.
+pub fn compress_inner_mode(input: &[u8], out: &mut Vec<u8>, mode: u32) {
+	assert!(mode < 16, "unsupported compression mode: {mode}");
+	if mode == 0 {
+		out.extend(input);
+		return;
+	}
+
+	let max_count = (1 << mode) - 1;
+	let max_raw = (1 << (16 - mode)) - 1;
+	let window = max_raw + 1;
+	let write = |out: &mut Vec<u8>, count: usize, x: usize| {
+		out.extend((count as u16 | (x << mode) as u16).to_le_bytes());
+	};
+	let encode_raw = |last: &mut usize, i: usize, out: &mut Vec<u8>| {
 		while *last < i {
-			let size = (i - *last).min(255);
-			out.extend(&[0, size as u8]);
+			let size = (i - *last).min(max_raw);
+			write(out, 0, size);
 			out.extend(&input[*last..*last + size]);
 			*last += size;
 		}
-	}
+	};
+	let mut chains = (mode != 8).then(|| Chains::new(input.len()));
 	let mut last = 0;
 	let mut i = 0;
 
 	while i < input.len() {
-		if i - last == 255 {
-			encode_raw(&mut last, i, out, input);
+		if i - last == max_raw {
+			encode_raw(&mut last, i, out);
 			continue;
 		}
 
-		let (start, len) = (i.saturating_sub(256)..i)
-			.rev()
-			.map(|j| (j, count_equal(&input[i..input.len() - 1], &input[j..], 255)))
-			.max_by_key(|a| a.1)
-			.unwrap_or((0, 0));
+		// Mode 8 picks the farthest of the longest matches, like Falcom, while the other modes pick the nearest.
+		let mut scan = i.saturating_sub(window)..i;
+		let mut chained;
+		let candidates: &mut dyn Iterator<Item = usize> = match &mut chains {
+			Some(chains) => {
+				chains.insert_until(input, i);
+				chained = chains.candidates(input, i, window);
+				&mut chained
+			}
+			None => &mut scan,
+		};
+		let mut start = 0;
+		let mut len = 0;
+		for j in candidates {
+			let n = count_equal(&input[i..input.len() - 1], &input[j..], max_count);
+			if n > len {
+				(start, len) = (j, n);
+				if n == max_count {
+					break;
+				}
+			}
+		}
 
+		// For mode 8, the threshold of 252 pending raw bytes is what Falcom uses.
 		let threshold = if i == last { 2 } else { 4 };
-		if i - last < 252 && len >= threshold {
-			encode_raw(&mut last, i, out, input);
-			out.extend(&[len as u8, (i - start - 1) as u8, input[i + len]]);
+		if i - last < max_raw.saturating_sub(3).max(1) && len >= threshold {
+			encode_raw(&mut last, i, out);
+			write(out, len, i - start - 1);
+			out.push(input[i + len]);
 			i += len + 1;
 			last = i;
 		} else {
 			i += 1;
 		}
 	}
-	encode_raw(&mut last, i, out, input);
+	encode_raw(&mut last, i, out);
+}
+
+/// Hash chains over the two-byte prefixes of each position, so that the wide windows don't need a full scan.
+struct Chains {
+	head: Vec<u32>,
+	prev: Vec<u32>,
+	inserted: usize,
+}
+
+impl Chains {
+	const NONE: u32 = u32::MAX;
+
+	fn new(len: usize) -> Self {
+		Chains {
+			head: vec![Self::NONE; 0x10000],
+			prev: vec![Self::NONE; len],
+			inserted: 0,
+		}
+	}
+
+	fn digraph(input: &[u8], i: usize) -> Option<usize> {
+		Some(u16::from_le_bytes([*input.get(i)?, *input.get(i + 1)?]) as usize)
+	}
+
+	/// Adds all positions before `i`, including the ones that were skipped over by a repeat.
+	fn insert_until(&mut self, input: &[u8], i: usize) {
+		while self.inserted < i {
+			let p = self.inserted;
+			if let Some(dig) = Self::digraph(input, p) {
+				self.prev[p] = self.head[dig];
+				self.head[dig] = p as u32;
+			}
+			self.inserted += 1;
+		}
+	}
+
+	/// The earlier positions that start with the same two bytes as `i`, nearest first, at most `window` bytes back.
+	fn candidates<'a>(
+		&'a self,
+		input: &[u8],
+		i: usize,
+		window: usize,
+	) -> impl Iterator<Item = usize> + 'a {
+		let first = Self::digraph(input, i).map(|dig| self.head[dig]);
+		// `NONE` is out of bounds, so it ends the chain.
+		core::iter::successors(first, |&p| self.prev.get(p as usize).copied())
+			.map_while(|p| (p != Self::NONE).then_some(p as usize))
+			.take_while(move |&p| i - p <= window)
+	}
+}
+
+#[test]
+fn compress_should_roundtrip() {
+	for data in [vec![], vec![7; 1000], test_data(20000)] {
+		let compressed = compress(&data);
+		let mut out = Vec::new();
+		assert_eq!(decompress(&compressed, &mut out).unwrap(), compressed.len());
+		assert!(out == data);
+	}
+}
+
+#[test]
+fn compress_mode_should_roundtrip() {
+	let data = test_data(1500);
+	assert!(compress_mode(&data, 8) == compress(&data));
+	for mode in 0..16 {
+		let compressed = compress_mode(&data, mode);
+		let mut out = Vec::new();
+		assert_eq!(decompress(&compressed, &mut out).unwrap(), compressed.len());
+		assert!(out == data, "mode {mode}");
+	}
+}
+
+#[test]
+fn compress_mode_should_handle_wide_windows() {
+	let data = test_data(300000);
+	for mode in [1, 2, 4, 12] {
+		let compressed = compress_mode(&data, mode);
+		let mut out = Vec::new();
+		assert_eq!(decompress(&compressed, &mut out).unwrap(), compressed.len());
+		assert!(out == data, "mode {mode}");
+	}
+}
+
+#[test]
+fn mode1_should_store_raw_runs() {
+	let data = [7; 1000];
+	let compressed = compress_mode(&data, 1);
+	for token in tokens(&compressed) {
+		assert!(matches!(token.unwrap().kind, TokenKind::Raw(_)));
+	}
+	let mut out = Vec::new();
+	assert_eq!(decompress(&compressed, &mut out).unwrap(), compressed.len());
+	assert!(out == data);
+}
+
+#[test]
+fn decompress_into_should_fit_exactly() {
+	let data = test_data(5000);
+	let compressed = compress(&data);
+	let mut out = vec![0; data.len()];
+	assert_eq!(
+		decompress_into(&compressed, &mut out).unwrap(),
+		(compressed.len(), data.len())
+	);
+	assert!(out == data);
+	assert!(matches!(
+		decompress_into(&compressed, &mut out[1..]),
+		Err(Error::OutputLimit { limit: 4999 }),
+	));
+}
+
+#[test]
+fn decompress_should_enforce_declared_size() {
+	let data = [7; 1000];
+	let mut compressed = compress(&data);
+	compressed[4..8].copy_from_slice(&500u32.to_le_bytes());
+	assert!(matches!(
+		decompress(&compressed, &mut Vec::new()),
+		Err(Error::BadSize {
+			what: "c77 out_pos",
+			expected: 500,
+			..
+		}),
+	));
+}
+
+#[test]
+fn inspect_should_read_header() {
+	let mut data = vec![6, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0x12, 0x34];
+	assert_eq!(
+		inspect(&data),
+		Some(Info {
+			in_size: 6,
+			out_size: 2,
+			mode: 0
+		})
+	);
+	data[8] = 8;
+	assert_eq!(
+		inspect(&data),
+		Some(Info {
+			in_size: 6,
+			out_size: 2,
+			mode: 8
+		})
+	);
+	data[8] = 16;
+	assert_eq!(inspect(&data), None);
+	data[8] = 8;
+	data.push(0);
+	assert_eq!(inspect(&data), None);
+}
+
+#[test]
+fn tokens_should_replay_to_output() {
+	let data = test_data(3000);
+	for mode in [0, 4, 8, 12] {
+		let compressed = compress_mode(&data, mode);
+		let mut out = Vec::new();
+		let mut tokens = tokens(&compressed);
+		let mut pos = 12;
+		for token in &mut tokens {
+			let token = token.unwrap();
+			assert!(token.pos >= pos);
+			assert_eq!(token.out_pos, out.len());
+			match token.kind {
+				TokenKind::Raw(data) => out.extend_from_slice(data),
+				TokenKind::Repeat {
+					offset,
+					count,
+					literal,
+				} => {
+					for _ in 0..count {
+						out.push(out[out.len() - offset]);
+					}
+					out.push(literal);
+				}
+			}
+			pos = token.pos + 1;
+		}
+		assert!(out == data, "mode {mode}");
+		assert_eq!(tokens.pos(), compressed.len());
+	}
+	assert!(matches!(
+		tokens(&[1, 0, 0, 0]).next(),
+		Some(Err(Error::Read { pos: 4 }))
+	));
 }
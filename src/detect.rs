@@ -0,0 +1,131 @@
+use alloc::vec::Vec;
+
+#[cfg(test)]
+use crate::util::test_data;
+use crate::{bzip, c77, ed6, ed7, DecodeOptions, Error, Result};
+
+/// The framings that can be recognized by [`detect`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+	/// A single unframed chunk, as read by [`bzip::decompress`].
+	Bzip(bzip::CompressMode),
+	Ed6,
+	Ed7,
+	/// A `0x80000001` header followed by C77 chunks, as read by [`ed7::freadp`].
+	Freadp,
+	C77,
+}
+
+/// Guesses the framing of some compressed data by validating its headers and chunk structure.
+///
+/// The whole input must be consumed by the stream; trailing data is not allowed.
+/// The framed formats are checked first, since unframed bzip data can only be identified by decompressing it.
+pub fn detect(data: &[u8]) -> Option<Format> {
+	detect_with(data, DecodeOptions::default())
+}
+
+/// Like [`detect`], but with options such as a limit on the output size.
+///
+/// The limit applies while decompressing unframed bzip data to recognize it,
+/// so such data is not recognized at all if it exceeds the limit.
+pub fn detect_with(data: &[u8], options: DecodeOptions) -> Option<Format> {
+	if let Some(ed7::FreadpInfo::C77 { .. }) = ed7::inspect_freadp(data) {
+		Some(Format::Freadp)
+	} else if ed7::inspect(data).is_some() {
+		Some(Format::Ed7)
+	} else if c77::inspect(data).is_some() {
+		Some(Format::C77)
+	} else if ed6::inspect(data).is_some() {
+		Some(Format::Ed6)
+	} else if !data.is_empty()
+		&& bzip::decompress_with(data, &mut Vec::new(), options).ok()? == data.len()
+	{
+		if data[0] == 0 {
+			Some(Format::Bzip(bzip::CompressMode::Mode2))
+		} else {
+			Some(Format::Bzip(bzip::CompressMode::Mode1))
+		}
+	} else {
+		None
+	}
+}
+
+/// Decompresses data in any format recognized by [`detect`].
+///
+/// Returns the number of bytes read, like the format-specific functions.
+pub fn decompress_auto(data: &[u8], out: &mut Vec<u8>) -> Result<usize> {
+	decompress_auto_with(data, out, DecodeOptions::default())
+}
+
+/// Like [`decompress_auto`], but with options such as a limit on the output size.
+///
+/// The limit also applies while detecting unframed bzip data, which has to be decompressed to be recognized,
+/// so such data is not recognized at all if it exceeds the limit.
+pub fn decompress_auto_with(
+	data: &[u8],
+	out: &mut Vec<u8>,
+	options: DecodeOptions,
+) -> Result<usize> {
+	match detect_with(data, options) {
+		Some(Format::Bzip(_)) => bzip::decompress_with(data, out, options),
+		Some(Format::Ed6) => ed6::decompress_with(data, out, options),
+		Some(Format::Ed7) => ed7::decompress_with(data, out, options),
+		Some(Format::Freadp) => ed7::freadp_with(data, out, options),
+		Some(Format::C77) => c77::decompress_with(data, out, options),
+		None => Err(Error::Custom {
+			message: "unrecognized compression format".into(),
+		}),
+	}
+}
+
+#[test]
+fn should_detect_compressed() {
+	let data = test_data(100000);
+	let mode = bzip::CompressMode::Mode2;
+
+	let mut raw = Vec::new();
+	bzip::compress(&data[..0x8000], &mut raw, mode);
+	let small = &data[..20000];
+
+	for (compressed, format, data) in [
+		(raw, Format::Bzip(mode), &data[..0x8000]),
+		(ed6::compress(&data, mode), Format::Ed6, &data[..]),
+		(ed7::compress(&data, mode), Format::Ed7, &data[..]),
+		(c77::compress(small), Format::C77, small),
+		(
+			ed7::freadp_compress(
+				small,
+				ed7::FreadpMode::C77 {
+					chunk_size: 0x1000,
+					mode: 8,
+				},
+			),
+			Format::Freadp,
+			small,
+		),
+	] {
+		assert_eq!(detect(&compressed), Some(format));
+		let mut out = Vec::new();
+		assert_eq!(
+			decompress_auto(&compressed, &mut out).unwrap(),
+			compressed.len()
+		);
+		assert!(out == data);
+	}
+}
+
+#[test]
+fn detect_with_should_stop_at_limit() {
+	let mut compressed = Vec::new();
+	bzip::compress(&[0; 100000], &mut compressed, bzip::CompressMode::Mode1);
+	let format = Some(Format::Bzip(bzip::CompressMode::Mode1));
+	assert_eq!(detect(&compressed), format);
+	assert_eq!(
+		detect_with(&compressed, DecodeOptions::new().with_max_output(100000)),
+		format
+	);
+	assert_eq!(
+		detect_with(&compressed, DecodeOptions::new().with_max_output(1000)),
+		None
+	);
+}
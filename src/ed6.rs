@@ -1,9 +1,31 @@
-use gospel::read::{Le as _, Reader};
-use gospel::write::{Le as _, Writer};
+use alloc::vec::Vec;
 
-use crate::{bzip, Error, Result};
+#[cfg(feature = "std")]
+use crate::util::par_map;
+#[cfg(test)]
+use crate::util::test_data;
+use crate::util::{self, Output, Reader};
+use crate::{bzip, DecodeOptions, Error, Result};
 
 pub fn decompress(data: &[u8], out: &mut Vec<u8>) -> Result<usize> {
+	decompress_to(data, &mut out.into())
+}
+
+/// Like [`decompress`], but writes into a fixed-size buffer instead of a `Vec`.
+///
+/// Returns the number of bytes read and written, or [`Error::OutputLimit`] if the output does not fit in the buffer.
+pub fn decompress_into(data: &[u8], out: &mut [u8]) -> Result<(usize, usize)> {
+	util::decompress_into(out, |out| decompress_to(data, out))
+}
+
+/// Like [`decompress`], but with options such as a limit on the output size.
+///
+/// Fails with [`Error::OutputLimit`] as soon as the output would exceed `options.max_output`.
+pub fn decompress_with(data: &[u8], out: &mut Vec<u8>, options: DecodeOptions) -> Result<usize> {
+	util::decompress_with(out, options, |out| decompress_to(data, out))
+}
+
+fn decompress_to(data: &[u8], out: &mut Output) -> Result<usize> {
 	let mut f = Reader::new(data);
 	loop {
 		read_compressed_chunk(&mut f, out)?;
@@ -14,6 +36,27 @@ pub fn decompress(data: &[u8], out: &mut Vec<u8>) -> Result<usize> {
 	Ok(f.pos())
 }
 
+/// Like [`decompress`], but decompresses the chunks in parallel.
+///
+/// This first finds the chunk boundaries, decompresses them on all available threads,
+/// and then concatenates them.
+#[cfg(feature = "std")]
+pub fn decompress_parallel(data: &[u8], out: &mut Vec<u8>) -> Result<usize> {
+	let chunks = chunks(data).collect::<Result<Vec<_>>>()?;
+	let outs = par_map(
+		&chunks,
+		|| (),
+		|(), chunk| {
+			let mut out = Vec::with_capacity(0xFFF0);
+			chunk.decompress(&mut out).map(|_| out)
+		},
+	);
+	for chunk_out in outs {
+		out.extend(chunk_out?);
+	}
+	Ok(chunks.last().map_or(0, |chunk| chunk.range.end + 1))
+}
+
 pub fn inspect(data: &[u8]) -> Option<(usize, Option<bzip::CompressMode>)> {
 	let f = &mut Reader::new(data);
 	let mut len = 0;
@@ -53,16 +96,118 @@ pub fn inspect(data: &[u8]) -> Option<(usize, Option<bzip::CompressMode>)> {
 	Some((len, mode))
 }
 
+/// Compresses data in `0xFFF0`-byte chunks.
+///
+/// # Panics
+///
+/// Panics if a compressed chunk does not fit in its `u16` length prefix, which happens with incompressible data.
+/// Use [`try_compress`] to get an error instead.
 pub fn compress(data: &[u8], mode: bzip::CompressMode) -> Vec<u8> {
-	let mut f = Writer::new();
-	let mut nchunks = data.chunks(0xFFF0).count();
+	try_compress(data, mode).unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// Like [`compress`], but fails with [`Error::ChunkTooLarge`] rather than panicking.
+pub fn try_compress(data: &[u8], mode: bzip::CompressMode) -> Result<Vec<u8>> {
+	let mut compressor = bzip::Compressor::new();
+	compress_by(data, |_, chunk, out| compressor.compress(chunk, out, mode))
+}
+
+/// Like [`compress`], but compresses the chunks in parallel. The output is identical.
+///
+/// # Panics
+///
+/// Panics in the same cases as [`compress`]. Use [`try_compress_parallel`] to get an error instead.
+#[cfg(feature = "std")]
+pub fn compress_parallel(data: &[u8], mode: bzip::CompressMode) -> Vec<u8> {
+	try_compress_parallel(data, mode).unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// Like [`compress_parallel`], but fails with [`Error::ChunkTooLarge`] rather than panicking.
+#[cfg(feature = "std")]
+pub fn try_compress_parallel(data: &[u8], mode: bzip::CompressMode) -> Result<Vec<u8>> {
+	let chunks = split(data).collect::<Vec<_>>();
+	let mut compressed = par_map(&chunks, bzip::Compressor::new, |compressor, chunk| {
+		let mut out = Vec::new();
+		compressor.compress(chunk, &mut out, mode);
+		out
+	})
+	.into_iter();
+	compress_by(data, |_, _, out| *out = compressed.next().unwrap())
+}
+
+/// Compresses data using a separate mode for each `0xFFF0`-byte chunk,
+/// for example to reproduce files that mix both modes.
+///
+/// # Panics
+///
+/// Panics if the number of modes does not match the number of chunks, or in the same cases as [`compress`].
+/// Use [`try_compress_mixed`] to get an error instead.
+pub fn compress_mixed(data: &[u8], modes: &[bzip::CompressMode]) -> Vec<u8> {
+	try_compress_mixed(data, modes).unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// Like [`compress_mixed`], but fails rather than panicking: with [`Error::BadSize`] if the number of modes
+/// does not match the number of chunks, and with [`Error::ChunkTooLarge`] in the same cases as [`try_compress`].
+pub fn try_compress_mixed(data: &[u8], modes: &[bzip::CompressMode]) -> Result<Vec<u8>> {
+	Error::check_size("number of ed6 modes", split(data).count(), modes.len())?;
+	let mut compressor = bzip::Compressor::new();
+	compress_by(data, |i, chunk, out| {
+		compressor.compress(chunk, out, modes[i])
+	})
+}
+
+/// Compresses each chunk in both modes, and keeps whichever is smaller.
+///
+/// This does not correspond to anything Falcom's tools do, so the output is unlikely to match theirs.
+///
+/// # Panics
+///
+/// Panics in the same cases as [`compress`]. Use [`try_compress_smallest`] to get an error instead.
+pub fn compress_smallest(data: &[u8]) -> Vec<u8> {
+	try_compress_smallest(data).unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// Like [`compress_smallest`], but fails with [`Error::ChunkTooLarge`] rather than panicking.
+pub fn try_compress_smallest(data: &[u8]) -> Result<Vec<u8>> {
+	let mut compressor = bzip::Compressor::new();
+	let mut mode2 = Vec::new();
+	compress_by(data, |_, chunk, out| {
+		compressor.compress(chunk, out, bzip::CompressMode::Mode1);
+		mode2.clear();
+		compressor.compress(chunk, &mut mode2, bzip::CompressMode::Mode2);
+		if mode2.len() <= out.len() {
+			core::mem::swap(out, &mut mode2);
+		}
+	})
+}
+
+/// Splits data into chunks. Empty data is a single empty chunk, since the stream can't have zero chunks.
+fn split(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+	data.chunks(0xFFF0).chain(data.is_empty().then_some(data))
+}
+
+fn compress_by(
+	data: &[u8],
+	mut compress_chunk: impl FnMut(usize, &[u8], &mut Vec<u8>),
+) -> Result<Vec<u8>> {
+	let mut f = Vec::new();
+	let mut nchunks = split(data).count();
 	let mut scratch = Vec::new();
-	for chunk in data.chunks(0xFFF0) {
-		write_compressed_chunk(&mut f, chunk, mode, &mut scratch);
+	for (i, chunk) in split(data).enumerate() {
+		scratch.clear();
+		compress_chunk(i, chunk, &mut scratch);
+		f.extend(length_prefix(&scratch)?);
+		f.extend(&scratch);
 		nchunks -= 1;
-		f.u8(nchunks as u8);
+		f.push(nchunks as u8);
 	}
-	f.finish().unwrap()
+	Ok(f)
+}
+
+pub(crate) fn length_prefix(chunk: &[u8]) -> Result<[u8; 2]> {
+	let len =
+		u16::try_from(chunk.len() + 2).map_err(|_| Error::ChunkTooLarge { len: chunk.len() })?;
+	Ok(len.to_le_bytes())
 }
 
 pub(crate) fn run(f: &mut Reader, mut func: impl FnMut(&[u8]) -> Result<usize>) -> Result<usize> {
@@ -71,17 +216,467 @@ pub(crate) fn run(f: &mut Reader, mut func: impl FnMut(&[u8]) -> Result<usize>)
 	Ok(len)
 }
 
-pub(crate) fn read_compressed_chunk(f: &mut Reader, out: &mut Vec<u8>) -> Result<usize> {
+pub(crate) fn read_compressed_chunk(f: &mut Reader, out: &mut Output) -> Result<usize> {
+	let chunk_len = f.u16()? as usize;
+	Error::check_size("chunk length", chunk_len.max(2), chunk_len)?;
+	// Mode 1 has no end marker, so it must not be given more than the chunk.
+	decompress_chunk(f.slice(chunk_len - 2)?, out)
+}
+
+pub(crate) fn decompress_chunk(chunk: &[u8], out: &mut Output) -> Result<usize> {
 	let start = out.len();
-	let expected_chunk_end = f.pos() + f.u16()? as usize;
-	run(f, |data| bzip::decompress(data, out))?;
-	Error::check_size("chunk in_pos", expected_chunk_end, f.pos())?;
+	let len = bzip::decompress_to(chunk, out)?;
+	Error::check_size("chunk in_pos", chunk.len(), len)?;
 	Ok(out.len() - start)
 }
 
-pub(crate) fn write_compressed_chunk(f: &mut Writer, chunk: &[u8], mode: bzip::CompressMode, scratch: &mut Vec<u8>) {
+/// Returns an iterator over the chunks of an ed6 stream.
+///
+/// Iteration stops after the first chunk that is not followed by a nonzero byte, or after the first error.
+pub fn chunks(data: &[u8]) -> Chunks<'_> {
+	Chunks::new(data, 0)
+}
+
+/// A single chunk in an ed6 or ed7 stream.
+#[derive(Debug, Clone)]
+pub struct Chunk<'a> {
+	/// The position of the compressed data in the stream, excluding the length prefix.
+	pub range: core::ops::Range<usize>,
+	pub data: &'a [u8],
+	pub mode: bzip::CompressMode,
+	/// The byte following the chunk. This is nonzero if more chunks follow.
+	pub next: u8,
+}
+
+impl Chunk<'_> {
+	/// Decompresses only this chunk, returning the number of bytes written.
+	pub fn decompress(&self, out: &mut Vec<u8>) -> Result<usize> {
+		decompress_chunk(self.data, &mut out.into())
+	}
+}
+
+/// An iterator over the chunks in an ed6 or ed7 stream, returned by [`chunks`] and [`crate::ed7::chunks`].
+#[derive(Debug, Clone)]
+pub struct Chunks<'a> {
+	data: &'a [u8],
+	pos: usize,
+	done: bool,
+}
+
+impl<'a> Chunks<'a> {
+	pub(crate) fn new(data: &'a [u8], pos: usize) -> Self {
+		Chunks {
+			data,
+			pos,
+			done: false,
+		}
+	}
+
+	fn read_chunk(&mut self) -> Result<Chunk<'a>> {
+		let f = &mut Reader::new(self.data);
+		f.seek(self.pos)?;
+		let chunk_len = f.u16()? as usize;
+		Error::check_size("chunk length", chunk_len.max(2), chunk_len)?;
+		let start = f.pos();
+		let data = f.slice(chunk_len - 2)?;
+		let next = f.u8()?;
+		self.pos = f.pos();
+		Ok(Chunk {
+			range: start..start + data.len(),
+			data,
+			mode: if data.first() == Some(&0) {
+				bzip::CompressMode::Mode2
+			} else {
+				bzip::CompressMode::Mode1
+			},
+			next,
+		})
+	}
+}
+
+impl<'a> Iterator for Chunks<'a> {
+	type Item = Result<Chunk<'a>>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.done {
+			return None;
+		}
+		let chunk = self.read_chunk();
+		self.done = !matches!(chunk, Ok(Chunk { next: 1.., .. }));
+		Some(chunk)
+	}
+}
+
+impl core::iter::FusedIterator for Chunks<'_> {}
+
+#[cfg(feature = "std")]
+pub(crate) fn write_compressed_chunk(
+	f: &mut Vec<u8>,
+	chunk: &[u8],
+	mode: bzip::CompressMode,
+	compressor: &mut bzip::Compressor,
+	scratch: &mut Vec<u8>,
+) -> std::io::Result<()> {
 	scratch.clear();
-	bzip::compress(chunk, scratch, mode);
-	f.u16(scratch.len() as u16 + 2);
-	f.slice(scratch);
+	compressor.compress(chunk, scratch, mode);
+	let prefix = length_prefix(scratch)
+		.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+	f.extend(prefix);
+	f.extend(&*scratch);
+	Ok(())
+}
+
+/// A streaming decoder for ed6-framed data.
+///
+/// Chunks are read and decompressed one at a time as the caller reads,
+/// so memory use is bounded by the size of a single chunk.
+#[cfg(feature = "std")]
+pub struct Decoder<R> {
+	reader: R,
+	inbuf: Vec<u8>,
+	outbuf: Vec<u8>,
+	outpos: usize,
+	done: bool,
+}
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read> Decoder<R> {
+	pub fn new(reader: R) -> Self {
+		Decoder {
+			reader,
+			inbuf: Vec::new(),
+			outbuf: Vec::new(),
+			outpos: 0,
+			done: false,
+		}
+	}
+
+	pub fn get_ref(&self) -> &R {
+		&self.reader
+	}
+
+	pub fn into_inner(self) -> R {
+		self.reader
+	}
+
+	fn next_chunk(&mut self) -> std::io::Result<()> {
+		let mut head = [0; 2];
+		self.reader.read_exact(&mut head)?;
+		let chunklen = u16::from_le_bytes(head) as usize;
+		self.inbuf.clear();
+		self.inbuf.extend(head);
+		self.inbuf.resize(2 + chunklen.saturating_sub(2), 0);
+		self.reader.read_exact(&mut self.inbuf[2..])?;
+
+		self.outbuf.clear();
+		self.outpos = 0;
+		// Falcom's tools never write more than 0xFFF0 bytes per chunk, and the limit keeps memory use bounded.
+		Output::from(&mut self.outbuf)
+			.limited(0xFFF0, |out| {
+				read_compressed_chunk(&mut Reader::new(&self.inbuf), out)
+			})
+			.map_err(|e| match e {
+				Error::OutputLimit { .. } => std::io::Error::new(
+					std::io::ErrorKind::InvalidData,
+					"ed6 chunk decompresses to more than 0xFFF0 bytes",
+				),
+				e => std::io::Error::new(std::io::ErrorKind::InvalidData, e),
+			})?;
+
+		let mut next = [0; 1];
+		self.reader.read_exact(&mut next)?;
+		self.done = next[0] == 0;
+		Ok(())
+	}
+}
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read> std::io::Read for Decoder<R> {
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+		while self.outpos == self.outbuf.len() && !self.done {
+			self.next_chunk()?;
+		}
+		let n = buf.len().min(self.outbuf.len() - self.outpos);
+		buf[..n].copy_from_slice(&self.outbuf[self.outpos..][..n]);
+		self.outpos += n;
+		Ok(n)
+	}
+}
+
+/// A streaming encoder for ed6-framed data.
+///
+/// Since each chunk is followed by the number of remaining chunks,
+/// the total length of the data must be known up front.
+/// The output is identical to that of [`compress`].
+#[cfg(feature = "std")]
+pub struct Encoder<W> {
+	writer: W,
+	mode: bzip::CompressMode,
+	remaining: usize,
+	/// Empty data is written as a single empty chunk in [`Encoder::finish`].
+	empty: bool,
+	buf: Vec<u8>,
+	compressor: bzip::Compressor,
+	scratch: Vec<u8>,
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write> Encoder<W> {
+	pub fn new(writer: W, len: usize, mode: bzip::CompressMode) -> Self {
+		Encoder {
+			writer,
+			mode,
+			remaining: len,
+			empty: len == 0,
+			buf: Vec::with_capacity(len.min(0xFFF0)),
+			compressor: bzip::Compressor::new(),
+			scratch: Vec::new(),
+		}
+	}
+
+	pub fn get_ref(&self) -> &W {
+		&self.writer
+	}
+
+	/// Finishes the stream and returns the inner writer.
+	///
+	/// Fails if fewer bytes were written than was declared in [`Encoder::new`].
+	pub fn finish(mut self) -> std::io::Result<W> {
+		if self.remaining != 0 {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::UnexpectedEof,
+				format!("ed6 stream is missing {} bytes", self.remaining),
+			));
+		}
+		if self.empty {
+			self.write_chunk()?;
+		}
+		self.writer.flush()?;
+		Ok(self.writer)
+	}
+
+	fn write_chunk(&mut self) -> std::io::Result<()> {
+		let mut f = Vec::new();
+		write_compressed_chunk(
+			&mut f,
+			&self.buf,
+			self.mode,
+			&mut self.compressor,
+			&mut self.scratch,
+		)?;
+		f.push(self.remaining.div_ceil(0xFFF0) as u8);
+		self.writer.write_all(&f)?;
+		self.buf.clear();
+		Ok(())
+	}
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write> std::io::Write for Encoder<W> {
+	fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+		if data.is_empty() {
+			return Ok(0);
+		}
+		if self.remaining == 0 {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::InvalidInput,
+				"wrote more data than declared to ed6 stream",
+			));
+		}
+		let n = data.len().min(0xFFF0 - self.buf.len()).min(self.remaining);
+		self.buf.extend_from_slice(&data[..n]);
+		self.remaining -= n;
+		if self.buf.len() == 0xFFF0 || self.remaining == 0 {
+			self.write_chunk()?;
+		}
+		Ok(n)
+	}
+
+	fn flush(&mut self) -> std::io::Result<()> {
+		self.writer.flush()
+	}
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn decoder_should_match_decompress() {
+	use std::io::Read;
+
+	let data = test_data(200000);
+	let compressed = compress(&data, bzip::CompressMode::Mode2);
+	let mut out = Vec::new();
+	Decoder::new(compressed.as_slice())
+		.read_to_end(&mut out)
+		.unwrap();
+	assert!(out == data);
+}
+
+#[test]
+fn mode1_should_decompress_multiple_chunks() {
+	// Mode 1 has no end marker, so this fails if a chunk is decoded past its end.
+	let data = test_data(0x20000);
+	let compressed = compress(&data, bzip::CompressMode::Mode1);
+	let mut out = Vec::new();
+	assert_eq!(decompress(&compressed, &mut out).unwrap(), compressed.len());
+	assert!(out == data);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn decoder_should_reject_oversized_chunk() {
+	use std::io::Read;
+
+	let mut chunk = Vec::new();
+	bzip::compress(&[0; 0x10000], &mut chunk, bzip::CompressMode::Mode1);
+	let mut data = (chunk.len() as u16 + 2).to_le_bytes().to_vec();
+	data.extend(&chunk);
+	data.push(0);
+	let err = Decoder::new(&data[..])
+		.read_to_end(&mut Vec::new())
+		.unwrap_err();
+	assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn encoder_should_match_compress() {
+	use std::io::Write;
+
+	let data = test_data(200000);
+	let mut enc = Encoder::new(Vec::new(), data.len(), bzip::CompressMode::Mode2);
+	for chunk in data.chunks(12345) {
+		enc.write_all(chunk).unwrap();
+	}
+	assert!(enc.finish().unwrap() == compress(&data, bzip::CompressMode::Mode2));
+}
+
+#[test]
+fn empty_should_roundtrip() {
+	for mode in [bzip::CompressMode::Mode1, bzip::CompressMode::Mode2] {
+		let compressed = compress(&[], mode);
+		assert_eq!(
+			decompress(&compressed, &mut Vec::new()).unwrap(),
+			compressed.len()
+		);
+		#[cfg(feature = "std")]
+		assert!(Encoder::new(Vec::new(), 0, mode).finish().unwrap() == compressed);
+	}
+}
+
+#[test]
+fn mixed_should_roundtrip() {
+	use bzip::CompressMode::*;
+
+	let mut data = test_data(200000);
+	data[0x10000..0x18000].fill(0);
+	data[0x28000..0x28100].fill(1);
+	let modes = [Mode1, Mode2, Mode1, Mode2];
+	let compressed = compress_mixed(&data, &modes);
+	assert_eq!(inspect(&compressed), Some((data.len(), None)));
+	let mut out = Vec::new();
+	assert_eq!(decompress(&compressed, &mut out).unwrap(), compressed.len());
+	assert!(out == data);
+
+	let smallest = compress_smallest(&data);
+	assert!(smallest.len() <= compress(&data, Mode1).len());
+	assert!(smallest.len() <= compress(&data, Mode2).len());
+	out.clear();
+	assert_eq!(decompress(&smallest, &mut out).unwrap(), smallest.len());
+	assert!(out == data);
+}
+
+#[test]
+fn try_compress_mixed_should_reject_wrong_mode_count() {
+	let data = test_data(0x10000);
+	assert!(matches!(
+		try_compress_mixed(&data, &[bzip::CompressMode::Mode1]),
+		Err(Error::BadSize {
+			expected: 2,
+			actual: 1,
+			..
+		})
+	));
+}
+
+#[test]
+fn chunks_should_match_compress() {
+	use bzip::CompressMode::*;
+
+	let data = test_data(200000);
+	let modes = [Mode2, Mode1, Mode1, Mode2];
+	let compressed = compress_mixed(&data, &modes);
+	let chunks = chunks(&compressed).collect::<Result<Vec<_>>>().unwrap();
+	assert_eq!(chunks.iter().map(|c| c.mode).collect::<Vec<_>>(), modes);
+	assert_eq!(
+		chunks.iter().map(|c| c.next).collect::<Vec<_>>(),
+		[3, 2, 1, 0]
+	);
+	assert_eq!(chunks[3].range.end + 1, compressed.len());
+
+	let mut out = Vec::new();
+	for chunk in &chunks {
+		assert!(&compressed[chunk.range.clone()] == chunk.data);
+		chunk.decompress(&mut out).unwrap();
+	}
+	assert!(out == data);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn compress_parallel_should_match_serial() {
+	let data = test_data(300000);
+	for mode in [bzip::CompressMode::Mode1, bzip::CompressMode::Mode2] {
+		assert!(compress_parallel(&data, mode) == compress(&data, mode));
+	}
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn decompress_parallel_should_match_serial() {
+	let data = test_data(300000);
+	let compressed = compress(&data, bzip::CompressMode::Mode2);
+	let mut out = Vec::new();
+	assert_eq!(
+		decompress_parallel(&compressed, &mut out).unwrap(),
+		compressed.len()
+	);
+	assert!(out == data);
+}
+
+#[test]
+fn try_compress_should_reject_incompressible_chunk() {
+	let mut x = 1u32;
+	let data = (0..0xFFF0)
+		.map(|_| {
+			x ^= x << 13;
+			x ^= x >> 17;
+			x ^= x << 5;
+			x as u8
+		})
+		.collect::<Vec<_>>();
+	for mode in [bzip::CompressMode::Mode1, bzip::CompressMode::Mode2] {
+		assert!(matches!(
+			try_compress(&data, mode),
+			Err(Error::ChunkTooLarge { .. })
+		));
+		#[cfg(feature = "std")]
+		{
+			use std::io::Write;
+
+			assert!(matches!(
+				try_compress_parallel(&data, mode),
+				Err(Error::ChunkTooLarge { .. })
+			));
+			let mut enc = Encoder::new(Vec::new(), data.len(), mode);
+			assert_eq!(
+				enc.write_all(&data).unwrap_err().kind(),
+				std::io::ErrorKind::InvalidData
+			);
+		}
+	}
+	assert!(matches!(
+		try_compress_smallest(&data),
+		Err(Error::ChunkTooLarge { .. })
+	));
+	assert!(try_compress(&data[..0xF000], bzip::CompressMode::Mode1).is_ok());
 }
@@ -1,91 +1,729 @@
-use gospel::read::{Le as _, Reader};
-use gospel::write::{Label, Le as _, Writer};
+use alloc::format;
+use alloc::vec::Vec;
 
-use crate::{bzip, c77, Error, Result};
-use crate::ed6::{read_compressed_chunk, run, write_compressed_chunk};
+use crate::ed6::{length_prefix, read_compressed_chunk, run};
+#[cfg(test)]
+use crate::util::test_data;
+use crate::util::{self, Output, Reader};
+use crate::{bzip, c77, ed6, DecodeOptions, Error, Result};
+#[cfg(feature = "std")]
+use crate::{ed6::write_compressed_chunk, util::par_map};
 
 pub fn decompress(data: &[u8], out: &mut Vec<u8>) -> Result<usize> {
+	decompress_to(data, &mut out.into())
+}
+
+/// Like [`decompress`], but writes into a fixed-size buffer instead of a `Vec`.
+///
+/// Dummy chunks are not written to the buffer, so a buffer of exactly the declared size is sufficient.
+/// Returns the number of bytes read and written, or [`Error::OutputLimit`] if the output does not fit in the buffer.
+pub fn decompress_into(data: &[u8], out: &mut [u8]) -> Result<(usize, usize)> {
+	util::decompress_into(out, |out| decompress_to(data, out))
+}
+
+/// Like [`decompress`], but with options such as a limit on the output size.
+///
+/// Fails with [`Error::OutputLimit`] as soon as the output would exceed `options.max_output`.
+pub fn decompress_with(data: &[u8], out: &mut Vec<u8>, options: DecodeOptions) -> Result<usize> {
+	util::decompress_with(out, options, |out| decompress_to(data, out))
+}
+
+fn decompress_to(data: &[u8], out: &mut Output) -> Result<usize> {
 	let f = &mut Reader::new(data);
-	let in_size = f.u32()? as usize;
-	let expected_in_pos = f.pos() + in_size;
-	let out_size = f.u32()? as usize;
-	let expected_out_len = out.len() + out_size;
-	let nchunks = f.u32()? as usize;
-	for n in 0..nchunks {
-		let chunk_len = read_compressed_chunk(f, out)?;
-
-		if out.len() > expected_out_len {
-			if chunk_len == 1 {
-				// Falcom's tools always write a chunk of one extra byte.
-				// In ao-psp cti03200, there's two.
-				out.pop();
-			} else {
+	let header = Header::read(f, out.len())?;
+	for n in 0..header.nchunks {
+		header.read_chunk(out, |out| read_compressed_chunk(f, out))?;
+		header.check_next(n, f.u8()?)?;
+	}
+	header.finish(f.pos(), out.len())?;
+	Ok(f.pos())
+}
+
+/// Like [`decompress`], but decompresses the chunks in parallel.
+///
+/// This first finds the chunk boundaries, decompresses them on all available threads,
+/// and then concatenates them.
+#[cfg(feature = "std")]
+pub fn decompress_parallel(data: &[u8], out: &mut Vec<u8>) -> Result<usize> {
+	let out = &mut Output::from(out);
+	let f = &mut Reader::new(data);
+	let header = Header::read(f, out.len())?;
+	let chunks = ed6::Chunks::new(data, f.pos())
+		.take(header.nchunks)
+		.collect::<Result<Vec<_>>>()?;
+	let outs = par_map(
+		&chunks,
+		|| (),
+		|(), chunk| {
+			let mut out = Vec::with_capacity(0x7FF0);
+			chunk.decompress(&mut out).map(|_| out)
+		},
+	);
+	for (n, (chunk, chunk_out)) in core::iter::zip(&chunks, outs).enumerate() {
+		let chunk_out = chunk_out?;
+		let result = header.read_chunk(out, |out| {
+			out.extend(&chunk_out)?;
+			Ok(chunk_out.len())
+		});
+		if result.is_err() {
+			// Nothing was written, so decode the chunk again to stop at the same point as the serial decoder.
+			header.read_chunk(out, |out| ed6::decompress_chunk(chunk.data, out))?;
+		}
+		header.check_next(n, chunk.next)?;
+	}
+	let end = chunks.last().map_or(f.pos(), |chunk| chunk.range.end + 1);
+	header.finish(end, out.len())?;
+	Ok(end)
+}
+
+struct Header {
+	expected_in_pos: usize,
+	expected_out_len: usize,
+	nchunks: usize,
+}
+
+impl Header {
+	fn read(f: &mut Reader, out_len: usize) -> Result<Header> {
+		let in_size = f.u32()? as usize;
+		let expected_in_pos = f.pos().saturating_add(in_size);
+		let out_size = f.u32()? as usize;
+		let expected_out_len = out_len.saturating_add(out_size);
+		let nchunks = f.u32()? as usize;
+		Ok(Header {
+			expected_in_pos,
+			expected_out_len,
+			nchunks,
+		})
+	}
+
+	fn read_chunk(
+		&self,
+		out: &mut Output,
+		read: impl FnOnce(&mut Output) -> Result<usize>,
+	) -> Result<()> {
+		if out.len() < self.expected_out_len {
+			// The declared size is enforced while decoding, so that it can't be used to hide a huge output.
+			out.declared("ed7 out_pos", self.expected_out_len, read)?;
+			return Ok(());
+		}
+		// Falcom's tools always write a chunk of one extra byte.
+		// In ao-psp cti03200, there's two.
+		// The chunk is decoded into a scratch buffer, so any limit hit here is on the chunk rather than the output.
+		let mut dummy = Vec::new();
+		let chunk_len = match Output::from(&mut dummy).limited(2, read) {
+			Err(Error::OutputLimit { .. }) => {
 				return Err(Error::Custom {
-					message: format!("unexpected chunk at end of data: {chunk_len} bytes"),
-				});
+					message: "unexpected chunk at end of data: more than 2 bytes".into(),
+				})
 			}
+			result => result?,
+		};
+		if chunk_len > 1 {
+			return Err(Error::Custom {
+				message: format!("unexpected chunk at end of data: {chunk_len} bytes"),
+			});
 		}
+		Ok(())
+	}
 
+	fn check_next(&self, n: usize, b: u8) -> Result<()> {
 		// Falcom's tools always have 0/1 here, but some other tool — might even be one of mine — writes other values.
-		let b = f.u8()?;
-		if (b != 0) != (n != nchunks - 1) {
+		if (b != 0) != (n + 1 != self.nchunks) {
 			return Err(Error::Custom {
-				message: format!("unexpected byte at chunk {}/{}: {}", n, nchunks - 1, b),
+				message: format!(
+					"unexpected byte at chunk {}/{}: {}",
+					n,
+					self.nchunks.saturating_sub(1),
+					b
+				),
 			});
 		}
+		Ok(())
 	}
 
-	Error::check_size("ed7 in_pos", expected_in_pos, f.pos())?;
-	Error::check_size("ed7 out_pos", expected_out_len, out.len())?;
-	Ok(f.pos())
+	fn finish(&self, in_pos: usize, out_len: usize) -> Result<()> {
+		Error::check_size("ed7 in_pos", self.expected_in_pos, in_pos)?;
+		Error::check_size("ed7 out_pos", self.expected_out_len, out_len)?;
+		Ok(())
+	}
+}
+
+/// Returns an iterator over the chunks of an ed7 stream, including any dummy chunks.
+///
+/// The header is not validated.
+pub fn chunks(data: &[u8]) -> ed6::Chunks<'_> {
+	ed6::Chunks::new(data, 12)
+}
+
+/// Information about an ed7 stream, as returned by [`inspect`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Info {
+	pub in_size: usize,
+	pub out_size: usize,
+	pub nchunks: usize,
+	/// The mode used by the chunks, or `None` if both modes are used.
+	pub mode: Option<bzip::CompressMode>,
+	pub dummy: Dummy,
+}
+
+/// Dummy chunks at the end of an ed7 stream, which are not part of the decompressed data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Dummy {
+	None,
+	/// A single one-byte chunk, as written by Falcom's tools and by [`compress`].
+	One,
+	/// Two one-byte chunks, as seen in ao-psp cti03200.
+	Two,
+}
+
+/// Reads the header and chunk structure of an ed7 stream without decompressing it.
+///
+/// Only the dummy chunks are decompressed. All other chunks are assumed to be `0x7FF0` bytes,
+/// except the last one which is inferred from the declared size.
+pub fn inspect(data: &[u8]) -> Option<Info> {
+	let f = &mut Reader::new(data);
+	let in_size = f.u32().ok()? as usize;
+	if f.remaining().len() != in_size {
+		return None;
+	}
+	let out_size = f.u32().ok()? as usize;
+	let nchunks = f.u32().ok()? as usize;
+
+	let mut chunks = Vec::with_capacity(nchunks.min(in_size / 3));
+	let mut has_mode1 = false;
+	let mut has_mode2 = false;
+	for n in 0..nchunks {
+		let chunklen = (f.u16().ok()? as usize).checked_sub(2)?;
+		let chunk = f.slice(chunklen).ok()?;
+		if chunk.is_empty() {
+			return None;
+		}
+		if chunk[0] == 0 {
+			has_mode2 = true;
+		} else {
+			has_mode1 = true;
+		}
+		if (f.u8().ok()? != 0) != (n + 1 != nchunks) {
+			return None;
+		}
+		chunks.push(chunk);
+	}
+	if !f.is_empty() {
+		return None;
+	}
+
+	let ndummy = nchunks.checked_sub(out_size.div_ceil(0x7FF0))?;
+	let dummy = match ndummy {
+		0 => Dummy::None,
+		1 => Dummy::One,
+		2 => Dummy::Two,
+		_ => return None,
+	};
+	for chunk in &chunks[nchunks - ndummy..] {
+		let mut vec = Vec::new();
+		if bzip::decompress(chunk, &mut vec).ok()? != chunk.len() || vec.len() != 1 {
+			return None;
+		}
+	}
+
+	let mode = match (has_mode1, has_mode2) {
+		(true, true) => None,
+		(true, false) => Some(bzip::CompressMode::Mode1),
+		(false, _) => Some(bzip::CompressMode::Mode2),
+	};
+
+	Some(Info {
+		in_size,
+		out_size,
+		nchunks,
+		mode,
+		dummy,
+	})
 }
 
 pub fn freadp(data: &[u8], out: &mut Vec<u8>) -> Result<usize> {
+	freadp_to(data, &mut out.into())
+}
+
+/// Like [`freadp`], but writes into a fixed-size buffer instead of a `Vec`.
+///
+/// Returns the number of bytes read and written, or [`Error::OutputLimit`] if the output does not fit in the buffer.
+pub fn freadp_into(data: &[u8], out: &mut [u8]) -> Result<(usize, usize)> {
+	util::decompress_into(out, |out| freadp_to(data, out))
+}
+
+/// Like [`freadp`], but with options such as a limit on the output size.
+///
+/// Fails with [`Error::OutputLimit`] as soon as the output would exceed `options.max_output`.
+pub fn freadp_with(data: &[u8], out: &mut Vec<u8>, options: DecodeOptions) -> Result<usize> {
+	util::decompress_with(out, options, |out| freadp_to(data, out))
+}
+
+fn freadp_to(data: &[u8], out: &mut Output) -> Result<usize> {
 	let f = &mut Reader::new(data);
 	if f.check_u32(0x80000001).is_ok() {
 		let n_chunks = f.u32()? as usize;
 		let in_size = f.u32()? as usize;
 		let buf_size = f.u32()? as usize;
 		let out_size = f.u32()? as usize;
-		let expected_in_pos = f.pos() + in_size;
-		let expected_out_len = out.len() + out_size;
+		let expected_in_pos = f.pos().saturating_add(in_size);
+		let expected_out_len = out.len().saturating_add(out_size);
 
 		let mut max_chunk_len = 0;
-		for _ in 0..n_chunks {
-			let chunk_len = run(f, |data| c77::decompress(data, out))?;
-			max_chunk_len = max_chunk_len.max(chunk_len);
-		}
+		out.declared("freadp out_pos", expected_out_len, |out| {
+			for _ in 0..n_chunks {
+				let chunk_len = run(f, |data| c77::decompress_to(data, out))?;
+				max_chunk_len = max_chunk_len.max(chunk_len);
+			}
+			Ok(())
+		})?;
 		Error::check_size("freadp buf_size", buf_size, max_chunk_len)?;
 		Error::check_size("freadp in_pos", expected_in_pos, f.pos())?;
 		Error::check_size("freadp out_pos", expected_out_len, out.len())?;
 	} else {
-		run(f, |data| decompress(data, out))?;
+		run(f, |data| decompress_to(data, out))?;
 	}
 	Ok(f.pos())
 }
 
+/// Information about a stream read by [`freadp`], as returned by [`inspect_freadp`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FreadpInfo {
+	/// A `0x80000001` header followed by a number of C77 chunks.
+	C77 {
+		in_size: usize,
+		/// The size of the largest chunk, including its header.
+		buf_size: usize,
+		out_size: usize,
+		chunks: Vec<c77::Info>,
+	},
+	/// A plain ed7 stream.
+	Ed7(Info),
+}
+
+/// Reads the headers of a stream that would be accepted by [`freadp`], without decompressing it.
+pub fn inspect_freadp(data: &[u8]) -> Option<FreadpInfo> {
+	let f = &mut Reader::new(data);
+	if f.check_u32(0x80000001).is_ok() {
+		let n_chunks = f.u32().ok()? as usize;
+		let in_size = f.u32().ok()? as usize;
+		let buf_size = f.u32().ok()? as usize;
+		let out_size = f.u32().ok()? as usize;
+		if f.remaining().len() != in_size {
+			return None;
+		}
+
+		let mut chunks = Vec::with_capacity(n_chunks.min(in_size / 12));
+		let mut max_chunk_len = 0;
+		let mut total_out = 0usize;
+		for _ in 0..n_chunks {
+			let start = f.pos();
+			let chunk = c77::read_info(f)?;
+			max_chunk_len = max_chunk_len.max(f.pos() - start);
+			total_out = total_out.checked_add(chunk.out_size)?;
+			chunks.push(chunk);
+		}
+		if !f.is_empty() || buf_size != max_chunk_len || out_size != total_out {
+			return None;
+		}
+		Some(FreadpInfo::C77 {
+			in_size,
+			buf_size,
+			out_size,
+			chunks,
+		})
+	} else {
+		inspect(data).map(FreadpInfo::Ed7)
+	}
+}
+
 pub fn compress(data: &[u8], mode: bzip::CompressMode) -> Vec<u8> {
-	let mut f = Writer::new();
-	let start = Label::new();
-	let end = Label::new();
+	let mut compressor = bzip::Compressor::new();
+	compress_by(data, |_, chunk, out| compressor.compress(chunk, out, mode))
+}
+
+/// Compresses data using a separate mode for each `0x7FF0`-byte chunk, including the trailing dummy chunk,
+/// for example to reproduce files that mix both modes.
+///
+/// # Panics
+///
+/// Panics if the number of modes does not match the number of chunks.
+/// Use [`try_compress_mixed`] to get an error instead.
+pub fn compress_mixed(data: &[u8], modes: &[bzip::CompressMode]) -> Vec<u8> {
+	try_compress_mixed(data, modes).unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// Like [`compress_mixed`], but fails with [`Error::BadSize`] rather than panicking
+/// if the number of modes does not match the number of chunks.
+pub fn try_compress_mixed(data: &[u8], modes: &[bzip::CompressMode]) -> Result<Vec<u8>> {
+	Error::check_size(
+		"number of ed7 modes",
+		data.chunks(0x7FF0).count() + 1,
+		modes.len(),
+	)?;
+	let mut compressor = bzip::Compressor::new();
+	Ok(compress_by(data, |i, chunk, out| {
+		compressor.compress(chunk, out, modes[i])
+	}))
+}
+
+/// Like [`compress`], but compresses the chunks in parallel. The output is identical.
+#[cfg(feature = "std")]
+pub fn compress_parallel(data: &[u8], mode: bzip::CompressMode) -> Vec<u8> {
+	let dummy = [dummy(data)];
+	let chunks = data.chunks(0x7FF0).chain([&dummy[..]]).collect::<Vec<_>>();
+	let mut compressed = par_map(&chunks, bzip::Compressor::new, |compressor, chunk| {
+		let mut out = Vec::new();
+		compressor.compress(chunk, &mut out, mode);
+		out
+	})
+	.into_iter();
+	compress_by(data, |_, _, out| *out = compressed.next().unwrap())
+}
+
+fn compress_by(data: &[u8], mut compress_chunk: impl FnMut(usize, &[u8], &mut Vec<u8>)) -> Vec<u8> {
+	let mut f = Vec::new();
 	let mut scratch = Vec::new();
-	f.diff32(start, end);
-	f.place(start);
-	f.u32(data.len() as u32);
-	f.u32(1 + data.chunks(0x7FF0).count() as u32);
+	let mut i = 0;
+	let mut write_chunk = |f: &mut Vec<u8>, chunk: &[u8]| {
+		scratch.clear();
+		compress_chunk(i, chunk, &mut scratch);
+		i += 1;
+		// Chunks of 0x7FF0 bytes always fit, even when incompressible.
+		f.extend(length_prefix(&scratch).expect("ed7 chunk fits in its length prefix"));
+		f.extend(&scratch);
+	};
+	f.extend(0u32.to_le_bytes());
+	f.extend((data.len() as u32).to_le_bytes());
+	f.extend((1 + data.chunks(0x7FF0).count() as u32).to_le_bytes());
 	for chunk in data.chunks(0x7FF0) {
-		write_compressed_chunk(&mut f, chunk, mode, &mut scratch);
-		f.u8(1);
+		write_chunk(&mut f, chunk);
+		f.push(1);
 	}
 
-	let dummy = *data
+	write_chunk(&mut f, &[dummy(data)]);
+	f.push(0);
+
+	let in_size = f.len() as u32 - 4;
+	f[..4].copy_from_slice(&in_size.to_le_bytes());
+	f
+}
+
+fn dummy(data: &[u8]) -> u8 {
+	*data
 		.chunks(0x7FF0)
 		.last()
 		.and_then(|a| a.first())
-		.unwrap_or(&0);
-	write_compressed_chunk(&mut f, &[dummy], mode, &mut scratch);
-	f.u8(0);
+		.unwrap_or(&0)
+}
+
+/// The framing written by [`freadp_compress`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FreadpMode {
+	/// A `0x80000001` header followed by C77 chunks of at most `chunk_size` bytes each,
+	/// compressed with [`c77::compress_mode`]. The chunk size must not be zero.
+	C77 { chunk_size: usize, mode: u32 },
+	/// A plain ed7 stream, as written by [`compress`].
+	Ed7(bzip::CompressMode),
+}
+
+/// Compresses data into a form that can be read by [`freadp`].
+///
+/// # Panics
+///
+/// Panics if a [`FreadpMode::C77`] chunk size is zero, or its mode is not supported by [`c77::compress_mode`].
+pub fn freadp_compress(data: &[u8], mode: FreadpMode) -> Vec<u8> {
+	let (chunk_size, mode) = match mode {
+		FreadpMode::C77 { chunk_size, mode } => (chunk_size, mode),
+		FreadpMode::Ed7(mode) => return compress(data, mode),
+	};
+	assert!(chunk_size > 0, "freadp chunk size must not be zero");
+	let chunks = data
+		.chunks(chunk_size)
+		.map(|chunk| c77::compress_mode(chunk, mode))
+		.collect::<Vec<_>>();
+	let mut f = Vec::new();
+	f.extend(0x80000001u32.to_le_bytes());
+	f.extend((chunks.len() as u32).to_le_bytes());
+	f.extend((chunks.iter().map(Vec::len).sum::<usize>() as u32).to_le_bytes());
+	f.extend((chunks.iter().map(Vec::len).max().unwrap_or(0) as u32).to_le_bytes());
+	f.extend((data.len() as u32).to_le_bytes());
+	for chunk in &chunks {
+		f.extend(chunk);
+	}
+	f
+}
+
+/// A streaming encoder for ed7-framed data.
+///
+/// The header is written as a placeholder and patched in [`Encoder::finish`],
+/// so the writer must be seekable.
+/// The output is identical to that of [`compress`].
+#[cfg(feature = "std")]
+pub struct Encoder<W> {
+	writer: W,
+	mode: bzip::CompressMode,
+	start: u64,
+	in_size: usize,
+	out_size: usize,
+	nchunks: usize,
+	dummy: u8,
+	buf: Vec<u8>,
+	compressor: bzip::Compressor,
+	scratch: Vec<u8>,
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write + std::io::Seek> Encoder<W> {
+	pub fn new(mut writer: W, mode: bzip::CompressMode) -> std::io::Result<Self> {
+		let start = writer.stream_position()?;
+		writer.write_all(&[0; 12])?;
+		Ok(Encoder {
+			writer,
+			mode,
+			start,
+			in_size: 8,
+			out_size: 0,
+			nchunks: 0,
+			dummy: 0,
+			buf: Vec::with_capacity(0x7FF0),
+			compressor: bzip::Compressor::new(),
+			scratch: Vec::new(),
+		})
+	}
+
+	pub fn get_ref(&self) -> &W {
+		&self.writer
+	}
+
+	/// Writes the remaining data and the trailing dummy chunk, and patches the header.
+	/// The writer is left positioned at the end of the stream.
+	pub fn finish(mut self) -> std::io::Result<W> {
+		if !self.buf.is_empty() {
+			self.write_chunk(1)?;
+		}
+		self.buf.push(self.dummy);
+		self.write_chunk(0)?;
+
+		let end = self.writer.stream_position()?;
+		self.writer.seek(std::io::SeekFrom::Start(self.start))?;
+		self.writer
+			.write_all(&(self.in_size as u32).to_le_bytes())?;
+		self.writer
+			.write_all(&(self.out_size as u32).to_le_bytes())?;
+		self.writer
+			.write_all(&(self.nchunks as u32).to_le_bytes())?;
+		self.writer.seek(std::io::SeekFrom::Start(end))?;
+		self.writer.flush()?;
+		Ok(self.writer)
+	}
+
+	fn write_chunk(&mut self, next: u8) -> std::io::Result<()> {
+		let mut chunk = Vec::new();
+		write_compressed_chunk(
+			&mut chunk,
+			&self.buf,
+			self.mode,
+			&mut self.compressor,
+			&mut self.scratch,
+		)?;
+		chunk.push(next);
+		self.writer.write_all(&chunk)?;
+		self.in_size += chunk.len();
+		self.nchunks += 1;
+		self.dummy = self.buf[0];
+		self.buf.clear();
+		Ok(())
+	}
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write + std::io::Seek> std::io::Write for Encoder<W> {
+	fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+		let n = data.len().min(0x7FF0 - self.buf.len());
+		self.buf.extend_from_slice(&data[..n]);
+		self.out_size += n;
+		if self.buf.len() == 0x7FF0 {
+			self.write_chunk(1)?;
+		}
+		Ok(n)
+	}
+
+	fn flush(&mut self) -> std::io::Result<()> {
+		self.writer.flush()
+	}
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn encoder_should_match_compress() {
+	use std::io::Write;
+
+	for len in [0, 1, 0x7FF0, 100000] {
+		let data = test_data(len);
+		let mut enc =
+			Encoder::new(std::io::Cursor::new(Vec::new()), bzip::CompressMode::Mode2).unwrap();
+		for chunk in data.chunks(12345) {
+			enc.write_all(chunk).unwrap();
+		}
+		let out = enc.finish().unwrap().into_inner();
+		assert!(out == compress(&data, bzip::CompressMode::Mode2));
+	}
+}
+
+#[test]
+fn inspect_should_match_compress() {
+	let data = test_data(100000);
+	let compressed = compress(&data, bzip::CompressMode::Mode2);
+	let info = inspect(&compressed).unwrap();
+	assert_eq!(info.in_size, compressed.len() - 4);
+	assert_eq!(info.out_size, data.len());
+	assert_eq!(info.nchunks, 5);
+	assert_eq!(info.mode, Some(bzip::CompressMode::Mode2));
+	assert_eq!(info.dummy, Dummy::One);
+}
+
+#[test]
+fn freadp_compress_should_roundtrip() {
+	let data = test_data(20000);
+	for mode in [
+		FreadpMode::C77 {
+			chunk_size: 0x1000,
+			mode: 8,
+		},
+		FreadpMode::Ed7(bzip::CompressMode::Mode2),
+	] {
+		let compressed = freadp_compress(&data, mode);
+		let mut out = Vec::new();
+		assert_eq!(freadp(&compressed, &mut out).unwrap(), compressed.len());
+		assert!(out == data);
+	}
+
+	let compressed = freadp_compress(
+		&data,
+		FreadpMode::C77 {
+			chunk_size: 0x1000,
+			mode: 8,
+		},
+	);
+	let Some(FreadpInfo::C77 {
+		out_size, chunks, ..
+	}) = inspect_freadp(&compressed)
+	else {
+		panic!("not a C77 freadp stream");
+	};
+	assert_eq!(out_size, data.len());
+	assert_eq!(chunks.len(), 5);
+}
+
+#[test]
+fn chunks_should_match_compress() {
+	let data = test_data(100000);
+	let compressed = compress(&data, bzip::CompressMode::Mode2);
+	let chunks = chunks(&compressed).collect::<Result<Vec<_>>>().unwrap();
+	assert_eq!(chunks.len(), inspect(&compressed).unwrap().nchunks);
+	let mut out = Vec::new();
+	for chunk in &chunks {
+		chunk.decompress(&mut out).unwrap();
+	}
+	assert_eq!(out.pop(), Some(data[0x7FF0 * 3]));
+	assert!(out == data);
+}
+
+#[test]
+fn mixed_should_match_chunk_modes() {
+	use bzip::CompressMode::*;
+
+	let data = test_data(100000);
+	let modes = [Mode2, Mode1, Mode1, Mode2, Mode1];
+	let compressed = compress_mixed(&data, &modes);
+	let chunks = chunks(&compressed).collect::<Result<Vec<_>>>().unwrap();
+	assert_eq!(chunks.iter().map(|c| c.mode).collect::<Vec<_>>(), modes);
+	assert_eq!(inspect(&compressed).unwrap().mode, None);
+	let mut out = Vec::new();
+	assert_eq!(decompress(&compressed, &mut out).unwrap(), compressed.len());
+	assert!(out == data);
+	assert!(compress_mixed(&data, &[Mode2; 5]) == compress(&data, Mode2));
+	assert!(matches!(
+		try_compress_mixed(&data, &[Mode2; 4]),
+		Err(Error::BadSize {
+			expected: 5,
+			actual: 4,
+			..
+		})
+	));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn compress_parallel_should_match_serial() {
+	for len in [0, 1, 0x7FF0, 300000] {
+		let data = test_data(len);
+		let mode = bzip::CompressMode::Mode2;
+		assert!(compress_parallel(&data, mode) == compress(&data, mode));
+	}
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn decompress_parallel_should_match_serial() {
+	let data = test_data(300000);
+	let mut compressed = compress(&data, bzip::CompressMode::Mode2);
+	let mut out = vec![1, 2, 3];
+	assert_eq!(
+		decompress_parallel(&compressed, &mut out).unwrap(),
+		compressed.len()
+	);
+	assert!(out[3..] == data);
+
+	compressed[40000] ^= 0x55;
+	let serial = decompress(&compressed, &mut Vec::new()).unwrap_err();
+	let parallel = decompress_parallel(&compressed, &mut Vec::new()).unwrap_err();
+	assert_eq!(serial.to_string(), parallel.to_string());
+}
+
+#[test]
+fn decompress_into_should_fit_exactly() {
+	let data = test_data(100000);
+	let compressed = compress(&data, bzip::CompressMode::Mode2);
+	let mut out = vec![0; data.len()];
+	assert_eq!(
+		decompress_into(&compressed, &mut out).unwrap(),
+		(compressed.len(), data.len())
+	);
+	assert!(out == data);
+	assert!(matches!(
+		decompress_into(&compressed, &mut out[1..]),
+		Err(Error::OutputLimit { limit: 99999 }),
+	));
+}
+
+#[test]
+fn decompress_should_enforce_declared_size() {
+	let data = test_data(100000);
+	let mut compressed = compress(&data, bzip::CompressMode::Mode2);
+	compressed[4..8].copy_from_slice(&50000u32.to_le_bytes());
+	let mut out = Vec::new();
+	assert!(matches!(
+		decompress(&compressed, &mut out),
+		Err(Error::BadSize { what: "ed7 out_pos", expected: 50000, actual }) if actual > 50000,
+	));
+	assert!(out.len() <= 50000);
+	let options = DecodeOptions::new().with_max_output(40000);
+	out.clear();
+	assert!(matches!(
+		decompress_with(&compressed, &mut out, options),
+		Err(Error::OutputLimit { limit: 40000 })
+	));
+}
 
-	f.place(end);
-	f.finish().unwrap()
+#[test]
+fn decompress_should_reject_large_trailing_chunk() {
+	let mut compressed = compress(&test_data(100), bzip::CompressMode::Mode2);
+	// The data chunk is now past the declared size, so it is read as a dummy chunk.
+	compressed[4..8].copy_from_slice(&0u32.to_le_bytes());
+	let err = decompress(&compressed, &mut Vec::new()).unwrap_err();
+	assert!(
+		matches!(&err, Error::Custom { message } if message.starts_with("unexpected chunk at end of data"))
+	);
 }
@@ -1,33 +1,44 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
 pub mod bzip;
 pub mod c77;
 
 pub mod ed6;
 pub mod ed7;
 
+mod detect;
+pub use detect::{decompress_auto, decompress_auto_with, detect, detect_with, Format};
+
+mod verify;
+pub use verify::{verify_roundtrip, Mismatch, MismatchToken, MismatchTokenKind};
+
 mod util;
 
 #[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum Error {
 	#[error("failed to read at position {pos}")]
-	Read {
-		pos: usize,
-	},
+	Read { pos: usize },
 	#[error("attempted to repeat {count} bytes from offset -{offset}, but only have {len} bytes")]
 	BadRepeat {
 		count: usize,
 		offset: usize,
 		len: usize,
 	},
+	#[error("output does not fit in {limit} bytes")]
+	OutputLimit { limit: usize },
 	#[error("wrong {what}: expected {expected}, got {actual}")]
-	BadSize { what: &'static str, expected: usize, actual: usize },
+	BadSize {
+		what: &'static str,
+		expected: usize,
+		actual: usize,
+	},
+	#[error("compressed chunk of {len} bytes does not fit in its length prefix")]
+	ChunkTooLarge { len: usize },
 	#[error("{message}")]
-	Custom { message: String },
-}
-
-impl From<gospel::read::Error> for Error {
-	fn from(e: gospel::read::Error) -> Self {
-		Error::Read { pos: e.pos() }
-	}
+	Custom { message: alloc::string::String },
 }
 
 impl Error {
@@ -35,9 +46,39 @@ impl Error {
 		if expected == actual {
 			Ok(())
 		} else {
-			Err(Error::BadSize { what, expected, actual })
+			Err(Error::BadSize {
+				what,
+				expected,
+				actual,
+			})
+		}
+	}
+}
+
+/// Options for the `decompress_with` functions.
+///
+/// Create them with [`DecodeOptions::new`] and the `with_` methods, so that options can be added later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub struct DecodeOptions {
+	/// The maximum number of bytes to write. Decoding stops with [`Error::OutputLimit`] as soon as this would be exceeded,
+	/// which guards against small inputs that expand to huge outputs.
+	pub max_output: Option<usize>,
+}
+
+impl DecodeOptions {
+	/// The default options, with no limits.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Sets [`DecodeOptions::max_output`].
+	pub fn with_max_output(self, max_output: usize) -> Self {
+		DecodeOptions {
+			max_output: Some(max_output),
+			..self
 		}
 	}
 }
 
-pub type Result<A, E = Error> = std::result::Result<A, E>;
+pub type Result<A, E = Error> = core::result::Result<A, E>;
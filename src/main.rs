@@ -0,0 +1,334 @@
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use falcompress::bzip::CompressMode;
+use falcompress::{bzip, c77, ed6, ed7, Format};
+
+type Result<T, E = Box<dyn std::error::Error>> = std::result::Result<T, E>;
+
+/// Compresses and decompresses Falcom's bzip, ed6, ed7, freadp and C77 formats.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+	#[command(subcommand)]
+	command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+	/// Compresses a file.
+	Compress {
+		#[arg(long, short)]
+		format: FormatArg,
+		#[command(flatten)]
+		options: CompressOptions,
+		#[command(flatten)]
+		io: Io,
+	},
+	/// Decompresses a file, detecting the format unless one is given.
+	Decompress {
+		#[arg(long, short)]
+		format: Option<FormatArg>,
+		#[command(flatten)]
+		io: Io,
+	},
+	/// Prints information about a compressed file, detecting the format unless one is given.
+	Inspect {
+		#[arg(long, short)]
+		format: Option<FormatArg>,
+		/// The file to read, or `-` for stdin.
+		input: Option<PathBuf>,
+	},
+	/// Checks whether a file is reproduced exactly when decompressed and recompressed.
+	Verify {
+		#[arg(long, short)]
+		format: Option<FormatArg>,
+		/// The file to read, or `-` for stdin.
+		input: Option<PathBuf>,
+	},
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum FormatArg {
+	Ed6,
+	Ed7,
+	Freadp,
+	C77,
+	Bzip,
+}
+
+#[derive(clap::Args)]
+struct CompressOptions {
+	/// The bzip mode, for the bzip, ed6 and ed7 formats.
+	#[arg(long, short, default_value_t = 2, value_parser = clap::value_parser!(u8).range(1..=2))]
+	mode: u8,
+	/// The C77 mode word, for the c77 and freadp formats.
+	#[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u32).range(0..16))]
+	c77_mode: u32,
+	/// The maximum number of bytes in each C77 chunk, for the freadp format.
+	#[arg(long, default_value_t = 0x10000, value_parser = clap::value_parser!(u32).range(1..))]
+	chunk_size: u32,
+}
+
+#[derive(clap::Args)]
+struct Io {
+	/// The file to read, or `-` for stdin.
+	input: Option<PathBuf>,
+	/// The file to write, or `-` for stdout.
+	#[arg(long, short)]
+	output: Option<PathBuf>,
+}
+
+fn main() -> ExitCode {
+	match run(Cli::parse().command) {
+		Ok(()) => ExitCode::SUCCESS,
+		Err(e) => {
+			eprintln!("falcompress: {e}");
+			ExitCode::FAILURE
+		}
+	}
+}
+
+fn run(command: Command) -> Result<()> {
+	match command {
+		Command::Compress {
+			format,
+			options,
+			io,
+		} => {
+			let data = read_input(io.input.as_ref())?;
+			write_output(io.output.as_ref(), &compress(&data, format, &options)?)
+		}
+		Command::Decompress { format, io } => {
+			let data = read_input(io.input.as_ref())?;
+			let format = match format {
+				Some(format) => format,
+				None => detect(&data)?,
+			};
+			let mut out = Vec::new();
+			let len = match format {
+				FormatArg::Ed6 => ed6::decompress_parallel(&data, &mut out)?,
+				FormatArg::Ed7 => ed7::decompress_parallel(&data, &mut out)?,
+				FormatArg::Freadp => ed7::freadp(&data, &mut out)?,
+				FormatArg::C77 => c77::decompress(&data, &mut out)?,
+				FormatArg::Bzip => bzip::decompress(&data, &mut out)?,
+			};
+			if len != data.len() {
+				eprintln!(
+					"falcompress: ignoring {} bytes of trailing data",
+					data.len() - len
+				);
+			}
+			write_output(io.output.as_ref(), &out)
+		}
+		Command::Inspect { format, input } => {
+			let data = read_input(input.as_ref())?;
+			let format = match format {
+				Some(format) => format,
+				None => detect(&data)?,
+			};
+			let mut stdout = std::io::stdout().lock();
+			inspect(&data, format, &mut stdout)?;
+			Ok(stdout.flush()?)
+		}
+		Command::Verify { format, input } => {
+			let data = read_input(input.as_ref())?;
+			let format = match format {
+				Some(format) => format,
+				None => detect(&data)?,
+			};
+			let format = match format {
+				FormatArg::Bzip if data.first() == Some(&0) => Format::Bzip(CompressMode::Mode2),
+				FormatArg::Bzip => Format::Bzip(CompressMode::Mode1),
+				FormatArg::Ed6 => Format::Ed6,
+				FormatArg::Ed7 => Format::Ed7,
+				FormatArg::Freadp => Format::Freadp,
+				FormatArg::C77 => Format::C77,
+			};
+			let Some(mismatch) = falcompress::verify_roundtrip(&data, format)? else {
+				println!("identical");
+				return Ok(());
+			};
+			let chunk = match mismatch.chunk {
+				Some(chunk) => format!("chunk {chunk}"),
+				None => "headers".to_owned(),
+			};
+			let mut message = format!(
+				"mismatch in {chunk} at offset {:#X} (original is {} bytes, recompressed is {} bytes)",
+				mismatch.offset, mismatch.original_len, mismatch.recompressed_len
+			);
+			if mismatch.original_token.is_some() || mismatch.recompressed_token.is_some() {
+				let describe = |token: Option<falcompress::MismatchToken>| match token {
+					Some(t) => format!(
+						"{:?} at offset {:#X}, output position {:#X}",
+						t.kind, t.pos, t.out_pos
+					),
+					None => "end of chunk".to_owned(),
+				};
+				message += &format!(
+					"\n  original token:     {}\n  recompressed token: {}",
+					describe(mismatch.original_token),
+					describe(mismatch.recompressed_token)
+				);
+			}
+			Err(message.into())
+		}
+	}
+}
+
+fn compress(data: &[u8], format: FormatArg, options: &CompressOptions) -> Result<Vec<u8>> {
+	let mode = if options.mode == 1 {
+		CompressMode::Mode1
+	} else {
+		CompressMode::Mode2
+	};
+	Ok(match format {
+		FormatArg::Ed6 => ed6::try_compress_parallel(data, mode)?,
+		FormatArg::Ed7 => ed7::compress_parallel(data, mode),
+		FormatArg::Freadp => {
+			let mode = ed7::FreadpMode::C77 {
+				chunk_size: options.chunk_size as usize,
+				mode: options.c77_mode,
+			};
+			ed7::freadp_compress(data, mode)
+		}
+		FormatArg::C77 => c77::compress_mode(data, options.c77_mode),
+		FormatArg::Bzip => {
+			if mode == CompressMode::Mode2 && data.len() >= 0xFFFF {
+				return Err(
+					"bzip mode 2 can only compress less than 0xFFFF bytes; use ed6 or ed7 instead"
+						.into(),
+				);
+			}
+			let mut out = Vec::new();
+			bzip::compress(data, &mut out, mode);
+			out
+		}
+	})
+}
+
+fn detect(data: &[u8]) -> Result<FormatArg> {
+	Ok(
+		match falcompress::detect(data).ok_or("unrecognized compression format")? {
+			Format::Bzip(_) => FormatArg::Bzip,
+			Format::Ed6 => FormatArg::Ed6,
+			Format::Ed7 => FormatArg::Ed7,
+			Format::Freadp => FormatArg::Freadp,
+			Format::C77 => FormatArg::C77,
+		},
+	)
+}
+
+fn inspect(data: &[u8], format: FormatArg, out: &mut impl Write) -> Result<()> {
+	let invalid = || {
+		format!(
+			"not a valid {} stream",
+			format.to_possible_value().unwrap().get_name()
+		)
+	};
+	match format {
+		FormatArg::Ed6 => {
+			let (out_size, mode) = ed6::inspect(data).ok_or_else(invalid)?;
+			writeln!(out, "format: ed6")?;
+			writeln!(out, "out_size: {out_size}")?;
+			writeln!(out, "chunks: {}", ed6::chunks(data).count())?;
+			writeln!(out, "mode: {}", mode_name(mode))?;
+		}
+		FormatArg::Ed7 => {
+			let info = ed7::inspect(data).ok_or_else(invalid)?;
+			writeln!(out, "format: ed7")?;
+			print_ed7(&info, out)?;
+		}
+		FormatArg::Freadp => match ed7::inspect_freadp(data).ok_or_else(invalid)? {
+			ed7::FreadpInfo::C77 {
+				in_size,
+				buf_size,
+				out_size,
+				chunks,
+			} => {
+				writeln!(out, "format: freadp")?;
+				writeln!(out, "in_size: {in_size}")?;
+				writeln!(out, "out_size: {out_size}")?;
+				writeln!(out, "buf_size: {buf_size}")?;
+				writeln!(out, "chunks: {}", chunks.len())?;
+				for (i, chunk) in chunks.iter().enumerate() {
+					writeln!(
+						out,
+						"  {i}: in_size {}, out_size {}, mode {}",
+						chunk.in_size, chunk.out_size, chunk.mode
+					)?;
+				}
+			}
+			ed7::FreadpInfo::Ed7(info) => {
+				writeln!(out, "format: freadp (ed7)")?;
+				print_ed7(&info, out)?;
+			}
+		},
+		FormatArg::C77 => {
+			let info = c77::inspect(data).ok_or_else(invalid)?;
+			writeln!(out, "format: c77")?;
+			writeln!(out, "in_size: {}", info.in_size)?;
+			writeln!(out, "out_size: {}", info.out_size)?;
+			writeln!(out, "mode: {}", info.mode)?;
+		}
+		FormatArg::Bzip => {
+			let mut decompressed = Vec::new();
+			let len = bzip::decompress(data, &mut decompressed)?;
+			let mode = if data.first() == Some(&0) {
+				CompressMode::Mode2
+			} else {
+				CompressMode::Mode1
+			};
+			writeln!(out, "format: bzip")?;
+			writeln!(out, "in_size: {len}")?;
+			writeln!(out, "out_size: {}", decompressed.len())?;
+			writeln!(out, "mode: {}", mode_name(Some(mode)))?;
+		}
+	}
+	Ok(())
+}
+
+fn print_ed7(info: &ed7::Info, out: &mut impl Write) -> std::io::Result<()> {
+	writeln!(out, "in_size: {}", info.in_size)?;
+	writeln!(out, "out_size: {}", info.out_size)?;
+	writeln!(out, "chunks: {}", info.nchunks)?;
+	writeln!(out, "mode: {}", mode_name(info.mode))?;
+	writeln!(out, "dummy: {:?}", info.dummy)
+}
+
+fn mode_name(mode: Option<CompressMode>) -> &'static str {
+	match mode {
+		Some(CompressMode::Mode1) => "1",
+		Some(CompressMode::Mode2) => "2",
+		None => "mixed",
+	}
+}
+
+fn read_input(path: Option<&PathBuf>) -> Result<Vec<u8>> {
+	match path {
+		Some(path) if path.as_os_str() != "-" => {
+			std::fs::read(path).map_err(|e| format!("{}: {e}", path.display()).into())
+		}
+		_ => {
+			let mut data = Vec::new();
+			std::io::stdin().lock().read_to_end(&mut data)?;
+			Ok(data)
+		}
+	}
+}
+
+fn write_output(path: Option<&PathBuf>, data: &[u8]) -> Result<()> {
+	match path {
+		Some(path) if path.as_os_str() != "-" => {
+			std::fs::write(path, data).map_err(|e| format!("{}: {e}", path.display()).into())
+		}
+		_ => {
+			let mut stdout = std::io::stdout().lock();
+			stdout.write_all(data)?;
+			stdout.flush()?;
+			Ok(())
+		}
+	}
+}
@@ -1,6 +1,78 @@
-use std::iter::zip;
+use alloc::vec::Vec;
+use core::iter::zip;
 
-use crate::{Error, Result};
+use crate::{DecodeOptions, Error, Result};
+
+/// A cursor over a byte slice, reading little-endian values.
+#[derive(Debug, Clone)]
+pub(crate) struct Reader<'a> {
+	data: &'a [u8],
+	pos: usize,
+}
+
+impl<'a> Reader<'a> {
+	pub(crate) fn new(data: &'a [u8]) -> Self {
+		Reader { data, pos: 0 }
+	}
+
+	pub(crate) fn pos(&self) -> usize {
+		self.pos
+	}
+
+	pub(crate) fn is_empty(&self) -> bool {
+		self.pos == self.data.len()
+	}
+
+	pub(crate) fn remaining(&self) -> &'a [u8] {
+		&self.data[self.pos..]
+	}
+
+	pub(crate) fn seek(&mut self, pos: usize) -> Result<()> {
+		if pos > self.data.len() {
+			return Err(Error::Read { pos });
+		}
+		self.pos = pos;
+		Ok(())
+	}
+
+	/// Reads a slice of the given length. On failure, the position is unchanged.
+	pub(crate) fn slice(&mut self, len: usize) -> Result<&'a [u8]> {
+		if len > self.remaining().len() {
+			return Err(Error::Read { pos: self.pos });
+		}
+		let pos = self.pos;
+		self.pos += len;
+		Ok(&self.data[pos..pos + len])
+	}
+
+	pub(crate) fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+		Ok(self.slice(N)?.try_into().unwrap())
+	}
+
+	pub(crate) fn u8(&mut self) -> Result<u8> {
+		Ok(self.array::<1>()?[0])
+	}
+
+	pub(crate) fn u16(&mut self) -> Result<u16> {
+		Ok(u16::from_le_bytes(self.array()?))
+	}
+
+	pub(crate) fn u32(&mut self) -> Result<u32> {
+		Ok(u32::from_le_bytes(self.array()?))
+	}
+
+	/// Reads a `u32` if it has the given value. Otherwise, the position is unchanged.
+	pub(crate) fn check_u32(&mut self, v: u32) -> Result<()> {
+		let pos = self.pos;
+		if self.u32()? != v {
+			self.pos = pos;
+			return Err(Error::Custom {
+				message: alloc::format!("expected {v:#X} at position {pos}"),
+			});
+		}
+		Ok(())
+	}
+}
 
 pub(crate) fn count_equal(a: &[u8], b: &[u8], limit: usize) -> usize {
 	let n = limit.min(a.len()).min(b.len());
@@ -22,41 +94,235 @@ pub(crate) fn count_equal(a: &[u8], b: &[u8], limit: usize) -> usize {
 	i + zip(a.1, b.1).take_while(|(a, b)| a == b).count()
 }
 
-pub(crate) struct OutBuf<'a> {
+/// Maps `func` over `items` using all available threads, giving each thread its own state from `init`.
+#[cfg(feature = "std")]
+pub(crate) fn par_map<T: Sync, S, R: Send>(
+	items: &[T],
+	init: impl Fn() -> S + Sync,
+	func: impl Fn(&mut S, &T) -> R + Sync,
+) -> Vec<R> {
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	let nthreads = std::thread::available_parallelism()
+		.map_or(1, |n| n.get())
+		.min(items.len());
+	let next = AtomicUsize::new(0);
+	let mut results = std::iter::repeat_with(|| None)
+		.take(items.len())
+		.collect::<Vec<_>>();
+	std::thread::scope(|s| {
+		let threads = (0..nthreads)
+			.map(|_| {
+				s.spawn(|| {
+					let mut state = init();
+					let mut out = Vec::new();
+					loop {
+						let i = next.fetch_add(1, Ordering::Relaxed);
+						let Some(item) = items.get(i) else { break };
+						out.push((i, func(&mut state, item)));
+					}
+					out
+				})
+			})
+			.collect::<Vec<_>>();
+		for thread in threads {
+			let out = thread
+				.join()
+				.unwrap_or_else(|e| std::panic::resume_unwind(e));
+			for (i, r) in out {
+				results[i] = Some(r);
+			}
+		}
+	});
+	results.into_iter().map(Option::unwrap).collect()
+}
+
+/// The destination of decompressed data, which fails with [`Error::OutputLimit`] rather than growing past its limit.
+pub(crate) struct Output<'a> {
+	buf: Buf<'a>,
 	start: usize,
-	vec: &'a mut Vec<u8>,
+	limit: usize,
+	/// The length that the last write past the limit would have reached.
+	overflow: usize,
+}
+
+/// Either a growable `Vec`, or a fixed-size slice and the number of bytes written to it.
+enum Buf<'a> {
+	Vec(&'a mut Vec<u8>),
+	Slice(&'a mut [u8], usize),
 }
 
-impl<'a> From<&'a mut Vec<u8>> for OutBuf<'a> {
+impl<'a> From<&'a mut Vec<u8>> for Output<'a> {
 	fn from(vec: &'a mut Vec<u8>) -> Self {
-		OutBuf {
+		Output {
 			start: vec.len(),
-			vec,
+			limit: usize::MAX,
+			overflow: 0,
+			buf: Buf::Vec(vec),
 		}
 	}
 }
 
-impl<'a> std::ops::Deref for OutBuf<'a> {
-	type Target = Vec<u8>;
-
-	fn deref(&self) -> &Self::Target {
-		self.vec
+impl<'a> From<&'a mut [u8]> for Output<'a> {
+	fn from(slice: &'a mut [u8]) -> Self {
+		Output {
+			start: 0,
+			limit: slice.len(),
+			overflow: 0,
+			buf: Buf::Slice(slice, 0),
+		}
 	}
 }
 
-impl<'a> std::ops::DerefMut for OutBuf<'a> {
-	fn deref_mut(&mut self) -> &mut Self::Target {
-		self.vec
+impl Output<'_> {
+	/// Restricts the number of bytes that can be written, as given by [`DecodeOptions::max_output`].
+	pub(crate) fn with_options(mut self, options: DecodeOptions) -> Self {
+		if let Some(max_output) = options.max_output {
+			self.limit = self.limit.min(self.start.saturating_add(max_output));
+		}
+		self
+	}
+
+	/// Runs `f` while the output is restricted to a length of at most `limit`.
+	pub(crate) fn limited<T>(
+		&mut self,
+		limit: usize,
+		f: impl FnOnce(&mut Self) -> Result<T>,
+	) -> Result<T> {
+		let old = self.limit;
+		self.limit = old.min(limit);
+		let result = f(self);
+		self.limit = old;
+		result
+	}
+
+	/// Like [`Output::limited`], but for a size declared in the data. If that is what is exceeded,
+	/// rather than a limit set by the caller, this fails with [`Error::BadSize`] instead of [`Error::OutputLimit`].
+	pub(crate) fn declared<T>(
+		&mut self,
+		what: &'static str,
+		expected: usize,
+		f: impl FnOnce(&mut Self) -> Result<T>,
+	) -> Result<T> {
+		let old = self.limit;
+		self.limited(expected, f).map_err(|e| match e {
+			Error::OutputLimit { .. } if expected < old => Error::BadSize {
+				what,
+				expected,
+				actual: self.overflow,
+			},
+			e => e,
+		})
+	}
+
+	pub(crate) fn len(&self) -> usize {
+		match &self.buf {
+			Buf::Vec(vec) => vec.len(),
+			Buf::Slice(_, len) => *len,
+		}
+	}
+
+	fn reserve(&mut self, count: usize) -> Result<()> {
+		if self.limit.saturating_sub(self.len()) < count {
+			self.overflow = self.len().saturating_add(count);
+			return Err(Error::OutputLimit {
+				limit: self.limit - self.start,
+			});
+		}
+		if let Buf::Vec(vec) = &mut self.buf {
+			vec.reserve(count);
+		}
+		Ok(())
+	}
+
+	pub(crate) fn extend(&mut self, data: &[u8]) -> Result<()> {
+		self.reserve(data.len())?;
+		match &mut self.buf {
+			Buf::Vec(vec) => vec.extend_from_slice(data),
+			Buf::Slice(slice, len) => {
+				slice[*len..][..data.len()].copy_from_slice(data);
+				*len += data.len();
+			}
+		}
+		Ok(())
+	}
+
+	fn constant(&mut self, count: usize, value: u8) -> Result<()> {
+		self.reserve(count)?;
+		match &mut self.buf {
+			Buf::Vec(vec) => vec.resize(vec.len() + count, value),
+			Buf::Slice(slice, len) => {
+				slice[*len..][..count].fill(value);
+				*len += count;
+			}
+		}
+		Ok(())
+	}
+
+	fn repeat(&mut self, count: usize, offset: usize) -> Result<()> {
+		self.reserve(count)?;
+		match &mut self.buf {
+			Buf::Vec(vec) => {
+				for _ in 0..count {
+					vec.push(vec[vec.len() - offset]);
+				}
+			}
+			Buf::Slice(slice, len) => {
+				for _ in 0..count {
+					slice[*len] = slice[*len - offset];
+					*len += 1;
+				}
+			}
+		}
+		Ok(())
 	}
 }
 
-impl OutBuf<'_> {
-	pub(crate) fn decomp_constant(&mut self, count: usize, value: u8) {
-		for _ in 0..count {
-			self.push(value);
+/// Runs a decompressor on a fixed-size buffer, returning the number of bytes read and written.
+pub(crate) fn decompress_into(
+	out: &mut [u8],
+	decompress: impl FnOnce(&mut Output) -> Result<usize>,
+) -> Result<(usize, usize)> {
+	let mut out = Output::from(out);
+	let len = decompress(&mut out)?;
+	Ok((len, out.len()))
+}
+
+/// Runs a decompressor on a `Vec`, subject to the given options.
+pub(crate) fn decompress_with(
+	out: &mut Vec<u8>,
+	options: DecodeOptions,
+	decompress: impl FnOnce(&mut Output) -> Result<usize>,
+) -> Result<usize> {
+	decompress(&mut Output::from(out).with_options(options))
+}
+
+/// An [`Output`] that only allows references back to the point where it was created.
+pub(crate) struct OutBuf<'a, 'b> {
+	start: usize,
+	out: &'a mut Output<'b>,
+}
+
+impl<'a, 'b> OutBuf<'a, 'b> {
+	pub(crate) fn new(out: &'a mut Output<'b>) -> Self {
+		OutBuf {
+			start: out.len(),
+			out,
 		}
 	}
 
+	pub(crate) fn len(&self) -> usize {
+		self.out.len()
+	}
+
+	pub(crate) fn extend(&mut self, data: &[u8]) -> Result<()> {
+		self.out.extend(data)
+	}
+
+	pub(crate) fn decomp_constant(&mut self, count: usize, value: u8) -> Result<()> {
+		self.out.constant(count, value)
+	}
+
 	pub(crate) fn decomp_repeat(&mut self, count: usize, offset: usize) -> Result<()> {
 		if !(1..=self.len() - self.start).contains(&offset) {
 			return Err(Error::BadRepeat {
@@ -65,9 +331,14 @@ impl OutBuf<'_> {
 				len: self.len(),
 			});
 		}
-		for _ in 0..count {
-			self.vec.push(self.vec[self.vec.len() - offset]);
-		}
-		Ok(())
+		self.out.repeat(count, offset)
 	}
 }
+
+/// Deterministic data with a mix of repeats and literals, used as a fixture by the tests.
+#[cfg(test)]
+pub(crate) fn test_data(len: usize) -> Vec<u8> {
+	(0..len as u32)
+		.map(|a| (a.wrapping_mul(a) >> 10) as u8)
+		.collect()
+}
@@ -0,0 +1,368 @@
+use alloc::vec::Vec;
+use core::ops::Range;
+
+#[cfg(test)]
+use crate::util::test_data;
+use crate::util::{count_equal, Reader};
+use crate::{bzip, c77, ed6, ed7, Error, Format, Result};
+
+/// Where a recompressed stream first differs from the original, as returned by [`verify_roundtrip`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+	/// The index of the first chunk that differs, or `None` if the chunks are identical but the headers or trailing data differ,
+	/// or if the format has no chunks.
+	pub chunk: Option<usize>,
+	/// The position of the first differing byte in the original data.
+	pub offset: usize,
+	pub original_len: usize,
+	pub recompressed_len: usize,
+	/// The first token in the chunk that differs from the recompressed one, or `None` if the chunk ends there.
+	///
+	/// Both tokens are `None` if the chunks have the same tokens, or if the mismatch is outside of the chunks.
+	pub original_token: Option<MismatchToken>,
+	pub recompressed_token: Option<MismatchToken>,
+}
+
+/// A token from [`bzip::tokens`] or [`c77::tokens`], as reported in a [`Mismatch`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MismatchToken {
+	pub kind: MismatchTokenKind,
+	/// The position of the token in its stream, that is, in the original or in the recompressed data.
+	pub pos: usize,
+	/// The number of bytes written by the earlier tokens in the chunk.
+	pub out_pos: usize,
+}
+
+/// The kind of a [`MismatchToken`].
+///
+/// Literal bytes are left out, since both streams decompress to the same data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MismatchTokenKind {
+	/// See [`bzip::TokenKind::Literal`] and [`c77::TokenKind::Raw`].
+	Literal { len: usize },
+	/// See [`bzip::TokenKind::Repeat`].
+	Repeat { offset: usize, count: usize },
+	/// See [`bzip::TokenKind::Constant`].
+	Constant { byte: u8, count: usize },
+	/// See [`bzip::TokenKind::RepeatLast`].
+	RepeatLast { offset: usize, count: usize },
+	/// See [`c77::TokenKind::Repeat`].
+	RepeatLiteral {
+		offset: usize,
+		count: usize,
+		literal: u8,
+	},
+	/// See [`bzip::TokenKind::End`].
+	End,
+}
+
+/// Decompresses data, recompresses it with the same mode and framing, and checks that the result is identical.
+///
+/// Chunks are compared before the headers, since a size difference in any chunk also changes the headers.
+/// Returns `None` if the data is reproduced exactly, or an error if it could not be decompressed.
+pub fn verify_roundtrip(data: &[u8], format: Format) -> Result<Option<Mismatch>> {
+	let recompressed = recompress(data, format)?;
+	let original_chunks = chunk_ranges(data, format);
+	let recompressed_chunks = chunk_ranges(&recompressed, format);
+	let mismatch = |chunk: Option<usize>, offset| {
+		let tokens = |data: &[u8], chunks: &[Range<usize>]| match chunk {
+			Some(i) => chunks
+				.get(i)
+				.map_or_else(Vec::new, |range| chunk_tokens(data, format, range.clone())),
+			None if matches!(format, Format::Bzip(_) | Format::C77) => {
+				chunk_tokens(data, format, 0..data.len())
+			}
+			None => Vec::new(),
+		};
+		let original_tokens = tokens(data, &original_chunks);
+		let recompressed_tokens = tokens(&recompressed, &recompressed_chunks);
+		let n = core::iter::zip(&original_tokens, &recompressed_tokens)
+			.take_while(|(a, b)| a.kind == b.kind)
+			.count();
+		let same = n == original_tokens.len() && n == recompressed_tokens.len();
+		Mismatch {
+			chunk,
+			offset,
+			original_len: data.len(),
+			recompressed_len: recompressed.len(),
+			original_token: original_tokens.get(n).copied().filter(|_| !same),
+			recompressed_token: recompressed_tokens.get(n).copied().filter(|_| !same),
+		}
+	};
+
+	for (i, (a, b)) in core::iter::zip(&original_chunks, &recompressed_chunks).enumerate() {
+		if let Some(n) = first_difference(&data[a.clone()], &recompressed[b.clone()]) {
+			return Ok(Some(mismatch(Some(i), a.start + n)));
+		}
+	}
+	if original_chunks.len() != recompressed_chunks.len() {
+		let i = original_chunks.len().min(recompressed_chunks.len());
+		let offset = match original_chunks.get(i) {
+			Some(a) => a.start,
+			None => original_chunks.last().map_or(0, |a| a.end),
+		};
+		return Ok(Some(mismatch(Some(i), offset)));
+	}
+	Ok(first_difference(data, &recompressed).map(|n| mismatch(None, n)))
+}
+
+fn recompress(data: &[u8], format: Format) -> Result<Vec<u8>> {
+	let mut out = Vec::new();
+	Ok(match format {
+		Format::Bzip(mode) => {
+			bzip::decompress(data, &mut out)?;
+			if mode == bzip::CompressMode::Mode2 && out.len() >= 0xFFFF {
+				return Err(Error::Custom {
+					message: "cannot recompress 0xFFFF or more bytes in a single mode 2 chunk"
+						.into(),
+				});
+			}
+			let mut compressed = Vec::new();
+			bzip::compress(&out, &mut compressed, mode);
+			compressed
+		}
+		Format::Ed6 => {
+			ed6::decompress(data, &mut out)?;
+			let modes = chunk_modes(ed6::chunks(data), out.len().div_ceil(0xFFF0).max(1));
+			ed6::try_compress_mixed(&out, &modes)?
+		}
+		Format::Ed7 => {
+			ed7::decompress(data, &mut out)?;
+			ed7::compress_mixed(&out, &ed7_modes(data, out.len()))
+		}
+		Format::Freadp => {
+			ed7::freadp(data, &mut out)?;
+			let mode = match ed7::inspect_freadp(data) {
+				Some(ed7::FreadpInfo::C77 { chunks, .. }) => ed7::FreadpMode::C77 {
+					chunk_size: chunks.first().map_or(1, |c| c.out_size.max(1)),
+					mode: chunks.first().map_or(8, |c| c.mode),
+				},
+				_ => return Ok(ed7::compress_mixed(&out, &ed7_modes(data, out.len()))),
+			};
+			ed7::freadp_compress(&out, mode)
+		}
+		Format::C77 => {
+			c77::decompress(data, &mut out)?;
+			let f = &mut Reader::new(data);
+			f.seek(8)?;
+			c77::compress_mode(&out, f.u32()?)
+		}
+	})
+}
+
+/// The mode of each chunk, padded with the last mode or truncated to the number of chunks the compressor will write.
+fn chunk_modes(chunks: ed6::Chunks, nchunks: usize) -> Vec<bzip::CompressMode> {
+	let modes = chunks
+		.map_while(|c| c.ok())
+		.map(|c| c.mode)
+		.collect::<Vec<_>>();
+	(0..nchunks)
+		.map(|i| modes.get(i).or(modes.last()).copied().unwrap_or_default())
+		.collect()
+}
+
+fn ed7_modes(data: &[u8], out_len: usize) -> Vec<bzip::CompressMode> {
+	// One more for the dummy chunk.
+	chunk_modes(ed7::chunks(data), out_len.div_ceil(0x7FF0) + 1)
+}
+
+/// The byte ranges of each chunk in the stream, including their framing.
+fn chunk_ranges(data: &[u8], format: Format) -> Vec<Range<usize>> {
+	let bzip_chunks = |chunks: ed6::Chunks| {
+		chunks
+			.map_while(|c| c.ok())
+			.map(|c| c.range.start - 2..c.range.end + 1)
+			.collect()
+	};
+	match format {
+		Format::Bzip(_) | Format::C77 => Vec::new(),
+		Format::Ed6 => bzip_chunks(ed6::chunks(data)),
+		Format::Ed7 => bzip_chunks(ed7::chunks(data)),
+		Format::Freadp if is_freadp_c77(data) => {
+			let f = &mut Reader::new(data);
+			let mut ranges = Vec::new();
+			let Ok(()) = f.seek(20) else { return ranges };
+			while let Ok(in_size) = f.u32() {
+				let start = f.pos() - 4;
+				if f.u32().and_then(|_| f.slice(in_size as usize)).is_err() {
+					break;
+				}
+				ranges.push(start..f.pos());
+			}
+			ranges
+		}
+		Format::Freadp => bzip_chunks(ed7::chunks(data)),
+	}
+}
+
+fn is_freadp_c77(data: &[u8]) -> bool {
+	data.starts_with(&0x80000001u32.to_le_bytes())
+}
+
+/// The tokens of a chunk as returned by [`chunk_ranges`], or of the whole stream if the format has no chunks.
+fn chunk_tokens(data: &[u8], format: Format, range: Range<usize>) -> Vec<MismatchToken> {
+	let is_c77 = match format {
+		Format::C77 => true,
+		Format::Freadp => is_freadp_c77(data),
+		_ => false,
+	};
+	if is_c77 {
+		return c77::tokens(&data[range.clone()])
+			.map_while(|t| t.ok())
+			.map(|t| MismatchToken {
+				kind: match t.kind {
+					c77::TokenKind::Raw(data) => MismatchTokenKind::Literal { len: data.len() },
+					c77::TokenKind::Repeat {
+						offset,
+						count,
+						literal,
+					} => MismatchTokenKind::RepeatLiteral {
+						offset,
+						count,
+						literal,
+					},
+				},
+				pos: range.start + t.pos,
+				out_pos: t.out_pos,
+			})
+			.collect();
+	}
+	// Skip the length prefix and the trailing byte.
+	let start = if matches!(format, Format::Bzip(_)) {
+		range.start
+	} else {
+		range.start + 2
+	};
+	let end = if matches!(format, Format::Bzip(_)) {
+		range.end
+	} else {
+		range.end - 1
+	};
+	bzip::tokens(&data[start..end])
+		.map_while(|t| t.ok())
+		.map(|t| MismatchToken {
+			kind: match t.kind {
+				bzip::TokenKind::Literal(data) => MismatchTokenKind::Literal { len: data.len() },
+				bzip::TokenKind::Repeat { offset, count } => {
+					MismatchTokenKind::Repeat { offset, count }
+				}
+				bzip::TokenKind::Constant { byte, count } => {
+					MismatchTokenKind::Constant { byte, count }
+				}
+				bzip::TokenKind::RepeatLast { offset, count } => {
+					MismatchTokenKind::RepeatLast { offset, count }
+				}
+				bzip::TokenKind::End => MismatchTokenKind::End,
+			},
+			pos: start + t.pos,
+			out_pos: t.out_pos,
+		})
+		.collect()
+}
+
+fn first_difference(a: &[u8], b: &[u8]) -> Option<usize> {
+	let n = count_equal(a, b, usize::MAX);
+	(n < a.len().max(b.len())).then_some(n)
+}
+
+#[test]
+fn verify_should_accept_compressed() {
+	let data = test_data(100000);
+	let small = &data[..5000];
+	let mut raw = Vec::new();
+	bzip::compress(&data[..0x8000], &mut raw, bzip::CompressMode::Mode1);
+	let modes = [bzip::CompressMode::Mode1, bzip::CompressMode::Mode2];
+	for (compressed, format) in [
+		(raw, Format::Bzip(bzip::CompressMode::Mode1)),
+		(ed6::compress_mixed(&data, &modes), Format::Ed6),
+		(ed7::compress(&data, bzip::CompressMode::Mode1), Format::Ed7),
+		(
+			ed7::compress_mixed(&data, &[modes[1], modes[0], modes[0], modes[1], modes[0]]),
+			Format::Ed7,
+		),
+		(
+			ed7::compress_mixed(&data, &[modes[1], modes[0], modes[1], modes[1], modes[0]]),
+			Format::Freadp,
+		),
+		(
+			ed7::freadp_compress(
+				small,
+				ed7::FreadpMode::C77 {
+					chunk_size: 0x800,
+					mode: 8,
+				},
+			),
+			Format::Freadp,
+		),
+		(c77::compress_mode(small, 4), Format::C77),
+	] {
+		assert_eq!(verify_roundtrip(&compressed, format).unwrap(), None);
+	}
+}
+
+#[test]
+fn verify_should_reject_oversized_mode2() {
+	let mut compressed = Vec::new();
+	bzip::compress(&[0; 0xFFFF], &mut compressed, bzip::CompressMode::Mode1);
+	assert!(verify_roundtrip(&compressed, Format::Bzip(bzip::CompressMode::Mode2)).is_err());
+}
+
+#[test]
+fn verify_should_find_first_mismatch() {
+	let data = test_data(100000);
+	let mode2 = ed7::compress(&data, bzip::CompressMode::Mode2);
+	// The second chunk with an encoder that Falcom's tools don't match.
+	let second = ed7::chunks(&mode2).nth(1).unwrap().unwrap().range;
+	let mut best = Vec::new();
+	bzip::Compressor::with_level(bzip::CompressLevel::Best).compress(
+		&data[0x7FF0..0xFFE0],
+		&mut best,
+		bzip::CompressMode::Mode2,
+	);
+	let prefix = (best.len() as u16 + 2).to_le_bytes();
+	let mut mixed = [
+		&mode2[..second.start - 2],
+		&prefix,
+		&best,
+		&mode2[second.end..],
+	]
+	.concat();
+	let in_size = mixed.len() as u32 - 4;
+	mixed[..4].copy_from_slice(&in_size.to_le_bytes());
+
+	let mismatch = verify_roundtrip(&mixed, Format::Ed7).unwrap().unwrap();
+	assert_eq!(mismatch.chunk, Some(1));
+	assert!(mismatch.offset >= second.start - 2);
+	assert_eq!(mismatch.original_len, mixed.len());
+	let (a, b) = (
+		mismatch.original_token.unwrap(),
+		mismatch.recompressed_token.unwrap(),
+	);
+	assert_ne!(a.kind, b.kind);
+	assert_eq!(a.out_pos, b.out_pos);
+	assert!(a.pos >= second.start && a.pos < second.end);
+
+	let trailing = [&mode2[..], &[0]].concat();
+	let mismatch = verify_roundtrip(&trailing, Format::Ed7).unwrap().unwrap();
+	assert_eq!((mismatch.chunk, mismatch.offset), (None, mode2.len()));
+	assert_eq!(
+		(mismatch.original_token, mismatch.recompressed_token),
+		(None, None)
+	);
+
+	let mut best = Vec::new();
+	bzip::Compressor::with_level(bzip::CompressLevel::Best).compress(
+		&data[..5000],
+		&mut best,
+		bzip::CompressMode::Mode1,
+	);
+	let mismatch = verify_roundtrip(&best, Format::Bzip(bzip::CompressMode::Mode1))
+		.unwrap()
+		.unwrap();
+	let (a, b) = (
+		mismatch.original_token.unwrap(),
+		mismatch.recompressed_token.unwrap(),
+	);
+	assert_ne!(a.kind, b.kind);
+	assert!(a.pos <= mismatch.offset);
+}
@@ -0,0 +1,80 @@
+//! Checks the compressors against small checked-in bitstreams, so that any change in their output is noticed.
+//! The files in `data/` were generated by this crate, from the input below.
+
+use falcompress::bzip::{self, CompressMode};
+use falcompress::{c77, ed6, ed7};
+
+fn input() -> Vec<u8> {
+	let mut data = Vec::new();
+	for i in 0..40u32 {
+		data.extend(
+			format!(
+				"Estelle and Joshua walk {} steps to Rolent.\n",
+				i * 37 % 101
+			)
+			.bytes(),
+		);
+		data.extend(std::iter::repeat_n(i as u8, (i * 13 % 50) as usize));
+		data.extend((0..i * 3).map(|a| (a.wrapping_mul(2654435761) >> 24) as u8));
+	}
+	data
+}
+
+fn check(
+	compressed: &[u8],
+	golden: &[u8],
+	decompress: fn(&[u8], &mut Vec<u8>) -> falcompress::Result<usize>,
+) {
+	assert!(compressed == golden);
+	let mut out = Vec::new();
+	assert_eq!(decompress(golden, &mut out).unwrap(), golden.len());
+	assert!(out == input());
+}
+
+#[test]
+fn bzip_should_match_golden() {
+	for (mode, golden) in [
+		(
+			CompressMode::Mode1,
+			&include_bytes!("data/golden.mode1")[..],
+		),
+		(
+			CompressMode::Mode2,
+			&include_bytes!("data/golden.mode2")[..],
+		),
+	] {
+		let mut compressed = Vec::new();
+		bzip::compress(&input(), &mut compressed, mode);
+		check(&compressed, golden, bzip::decompress);
+	}
+}
+
+#[test]
+fn ed6_should_match_golden() {
+	let compressed = ed6::compress(&input(), CompressMode::Mode1);
+	check(
+		&compressed,
+		include_bytes!("data/golden.ed6"),
+		ed6::decompress,
+	);
+}
+
+#[test]
+fn ed7_should_match_golden() {
+	let compressed = ed7::compress(&input(), CompressMode::Mode2);
+	check(
+		&compressed,
+		include_bytes!("data/golden.ed7"),
+		ed7::decompress,
+	);
+}
+
+#[test]
+fn c77_should_match_golden() {
+	let compressed = c77::compress(&input());
+	check(
+		&compressed,
+		include_bytes!("data/golden.c77"),
+		c77::decompress,
+	);
+}
@@ -0,0 +1,192 @@
+use std::io::{Cursor, Write};
+
+use falcompress::bzip::{self, CompressLevel, CompressMode, Compressor};
+use falcompress::{c77, ed6, ed7, Error};
+use proptest::prelude::*;
+
+const MODES: [CompressMode; 2] = [CompressMode::Mode1, CompressMode::Mode2];
+
+fn random(len: usize) -> impl Strategy<Value = Vec<u8>> {
+	prop::collection::vec(any::<u8>(), 0..len)
+}
+
+fn repetitive(len: usize) -> impl Strategy<Value = Vec<u8>> {
+	prop_oneof![
+		prop::collection::vec(0..4u8, 0..len),
+		(prop::collection::vec(any::<u8>(), 1..50), 1..len / 20).prop_map(|(a, n)| a.repeat(n)),
+	]
+}
+
+fn runs(len: usize) -> impl Strategy<Value = Vec<u8>> {
+	prop::collection::vec((any::<u8>(), 1..len), 0..8).prop_map(|runs| {
+		runs.into_iter()
+			.flat_map(|(b, n)| std::iter::repeat_n(b, n))
+			.collect()
+	})
+}
+
+/// A full ed6 chunk of random, and therefore incompressible, data.
+fn random_chunk() -> impl Strategy<Value = Vec<u8>> {
+	prop::collection::vec(any::<u8>(), 0xFFF0)
+}
+
+/// Random, repetitive or run-heavy data of roughly up to `len` bytes.
+fn data(len: usize) -> impl Strategy<Value = Vec<u8>> {
+	prop_oneof![random(len), repetitive(len * 2), runs(len * 2)]
+}
+
+fn check(
+	compressed: &[u8],
+	data: &[u8],
+	decompress: fn(&[u8], &mut Vec<u8>) -> falcompress::Result<usize>,
+) {
+	let mut out = Vec::new();
+	assert_eq!(decompress(compressed, &mut out).unwrap(), compressed.len());
+	assert!(out == data);
+}
+
+/// Writes the data to an ed6 [`ed6::Encoder`] in pieces of `step` bytes.
+fn ed6_encode(data: &[u8], mode: CompressMode, step: usize) -> std::io::Result<Vec<u8>> {
+	let mut encoder = ed6::Encoder::new(Vec::new(), data.len(), mode);
+	for piece in data.chunks(step) {
+		encoder.write_all(piece)?;
+	}
+	encoder.finish()
+}
+
+/// Writes the data to an ed7 [`ed7::Encoder`] in pieces of `step` bytes.
+fn ed7_encode(data: &[u8], mode: CompressMode, step: usize) -> std::io::Result<Vec<u8>> {
+	let mut encoder = ed7::Encoder::new(Cursor::new(Vec::new()), mode)?;
+	for piece in data.chunks(step) {
+		encoder.write_all(piece)?;
+	}
+	Ok(encoder.finish()?.into_inner())
+}
+
+/// Alternating modes for each chunk, starting with `first`.
+fn alternating(first: CompressMode, nchunks: usize) -> Vec<CompressMode> {
+	let other = if first == CompressMode::Mode1 {
+		CompressMode::Mode2
+	} else {
+		CompressMode::Mode1
+	};
+	(0..nchunks)
+		.map(|i| if i % 2 == 0 { first } else { other })
+		.collect()
+}
+
+proptest! {
+	#![proptest_config(ProptestConfig::with_cases(64))]
+
+	#[test]
+	fn bzip_should_roundtrip(data in data(2000)) {
+		for mode in MODES {
+			let mut compressed = Vec::new();
+			bzip::compress(&data, &mut compressed, mode);
+			check(&compressed, &data, bzip::decompress);
+			let mut out = vec![0; data.len()];
+			assert_eq!(bzip::decompress_into(&compressed, &mut out).unwrap(), (compressed.len(), data.len()));
+			assert!(out == data);
+		}
+	}
+
+	// The optimal parse is slow in debug builds too.
+	#[test]
+	fn bzip_best_should_roundtrip(data in data(500)) {
+		for mode in MODES {
+			let mut compressed = Vec::new();
+			Compressor::with_level(CompressLevel::Best).compress(&data, &mut compressed, mode);
+			check(&compressed, &data, bzip::decompress);
+		}
+	}
+
+	#[test]
+	fn ed6_should_roundtrip(data in data(2000), step in 1..3000usize) {
+		for mode in MODES {
+			let compressed = ed6::compress(&data, mode);
+			check(&compressed, &data, ed6::decompress);
+			assert!(ed6::compress_parallel(&data, mode) == compressed);
+			assert!(ed6::compress_mixed(&data, &[mode]) == compressed);
+			assert!(ed6_encode(&data, mode, step).unwrap() == compressed);
+		}
+		check(&ed6::compress_smallest(&data), &data, ed6::decompress);
+	}
+
+	#[test]
+	fn ed7_should_roundtrip(data in data(2000), step in 1..3000usize) {
+		for mode in MODES {
+			let compressed = ed7::compress(&data, mode);
+			check(&compressed, &data, ed7::decompress);
+			assert!(ed7::compress_parallel(&data, mode) == compressed);
+			assert!(ed7_encode(&data, mode, step).unwrap() == compressed);
+			check(&ed7::compress_mixed(&data, &alternating(mode, data.len().div_ceil(0x7FF0) + 1)), &data, ed7::decompress);
+			check(&ed7::freadp_compress(&data, ed7::FreadpMode::Ed7(mode)), &data, ed7::freadp);
+		}
+	}
+
+	// The mode 8 C77 compressor scans the whole window for each byte, which is slow in debug builds.
+	#[test]
+	fn c77_should_roundtrip(data in data(300), mode in 0..16u32, chunk_size in 1..3000usize) {
+		check(&c77::compress(&data), &data, c77::decompress);
+		check(&c77::compress_mode(&data, mode), &data, c77::decompress);
+		let compressed = ed7::freadp_compress(&data, ed7::FreadpMode::C77 { chunk_size, mode });
+		check(&compressed, &data, ed7::freadp);
+	}
+}
+
+proptest! {
+	#![proptest_config(ProptestConfig::with_cases(4))]
+
+	// Incompressible chunks don't fit in ed6's length prefix, but ed7's chunks are small enough to always fit.
+	#[test]
+	fn full_chunks_should_roundtrip_or_fail(data in random_chunk(), step in 1..0x10000usize) {
+		let too_large = |result: falcompress::Result<Vec<u8>>| matches!(result, Err(Error::ChunkTooLarge { .. }));
+		for mode in MODES {
+			assert!(too_large(ed6::try_compress(&data, mode)));
+			assert!(too_large(ed6::try_compress_parallel(&data, mode)));
+			assert!(too_large(ed6::try_compress_mixed(&data, &[mode])));
+			assert!(ed6_encode(&data, mode, step).is_err());
+
+			let compressed = ed7::compress(&data, mode);
+			check(&compressed, &data, ed7::decompress);
+			assert!(ed7::compress_parallel(&data, mode) == compressed);
+			assert!(ed7_encode(&data, mode, step).unwrap() == compressed);
+			check(&ed7::compress_mixed(&data, &alternating(mode, 4)), &data, ed7::decompress);
+		}
+		assert!(too_large(ed6::try_compress_smallest(&data)));
+	}
+}
+
+#[test]
+fn chunk_boundaries_should_roundtrip() {
+	for len in [
+		0,
+		1,
+		0x7FEF,
+		0x7FF0,
+		0x7FF1,
+		0xFFEF,
+		0xFFF0,
+		0xFFF1,
+		0xFFF0 * 2usize,
+	] {
+		let data = (0..len as u32)
+			.map(|a| (a.wrapping_mul(a) >> 12) as u8)
+			.collect::<Vec<_>>();
+		for mode in MODES {
+			check(&ed6::compress(&data, mode), &data, ed6::decompress);
+			check(&ed7::compress(&data, mode), &data, ed7::decompress);
+			let ed6_modes = alternating(mode, len.div_ceil(0xFFF0).max(1));
+			check(
+				&ed6::compress_mixed(&data, &ed6_modes),
+				&data,
+				ed6::decompress,
+			);
+			check(
+				&ed7::compress_mixed(&data, &alternating(mode, len.div_ceil(0x7FF0) + 1)),
+				&data,
+				ed7::decompress,
+			);
+		}
+	}
+}